@@ -0,0 +1,98 @@
+#![no_std]
+
+//! Climate values derived from temperature in °C and relative humidity in %.
+//!
+//! Relative humidity is clamped to 0.1..=100 %, as the HTU21 may report values slightly outside 0..=100 %.
+
+use libm::{expf, logf};
+
+/** Magnus formula coefficients over water, valid from -45 °C to 60 °C. */
+const MAGNUS_A: f32 = 17.62;
+const MAGNUS_B: f32 = 243.12;
+/** Saturation vapor pressure at 0 °C in hPa. */
+const MAGNUS_C: f32 = 6.112;
+
+fn clamp_humidity(humidity: f32) -> f32 {
+    return humidity.clamp(0.1, 100.0);
+}
+
+/// Saturation vapor pressure in hPa.
+pub fn saturation_vapor_pressure(temperature: f32) -> f32 {
+    return MAGNUS_C * expf(MAGNUS_A * temperature / (MAGNUS_B + temperature));
+}
+
+/// Dew point in °C.
+pub fn dew_point(temperature: f32, humidity: f32) -> f32 {
+    let gamma = logf(clamp_humidity(humidity) / 100.0) + MAGNUS_A * temperature / (MAGNUS_B + temperature);
+    return MAGNUS_B * gamma / (MAGNUS_A - gamma);
+}
+
+/// Absolute humidity in g/m³.
+pub fn absolute_humidity(temperature: f32, humidity: f32) -> f32 {
+    let vapor_pressure = clamp_humidity(humidity) / 100.0 * saturation_vapor_pressure(temperature);
+    return 216.7 * vapor_pressure / (273.15 + temperature);
+}
+
+/// Vapor pressure deficit in kPa.
+pub fn vapor_pressure_deficit(temperature: f32, humidity: f32) -> f32 {
+    return saturation_vapor_pressure(temperature) * (1.0 - clamp_humidity(humidity) / 100.0) / 10.0;
+}
+
+/// Heat index (apparent temperature) in °C, after the NOAA Rothfusz regression with its adjustments.
+#[allow(clippy::excessive_precision)]
+pub fn heat_index(temperature: f32, humidity: f32) -> f32 {
+    let t = temperature * 9.0 / 5.0 + 32.0;
+    let rh = clamp_humidity(humidity);
+    let simple = 0.5 * (t + 61.0 + (t - 68.0) * 1.2 + rh * 0.094);
+    let fahrenheit = if (simple + t) / 2.0 < 80.0 {
+        simple
+    } else {
+        let mut hi = -42.379 + 2.04901523 * t + 10.14333127 * rh - 0.22475541 * t * rh
+            - 0.00683783 * t * t - 0.05481717 * rh * rh + 0.00122874 * t * t * rh
+            + 0.00085282 * t * rh * rh - 0.00000199 * t * t * rh * rh;
+        if rh < 13.0 && (80.0..=112.0).contains(&t) {
+            hi -= (13.0 - rh) / 4.0 * libm::sqrtf((17.0 - libm::fabsf(t - 95.0)) / 17.0);
+        } else if rh > 85.0 && (80.0..=87.0).contains(&t) {
+            hi += (rh - 85.0) / 10.0 * (87.0 - t) / 5.0;
+        }
+        hi
+    };
+    return (fahrenheit - 32.0) * 5.0 / 9.0;
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{absolute_humidity, dew_point, heat_index, saturation_vapor_pressure, vapor_pressure_deficit};
+
+    fn assert_near(actual: f32, expected: f32) {
+        assert!((actual - expected).abs() < 0.01, "{} is not near {}", actual, expected);
+    }
+
+    #[test]
+    fn saturation_vapor_pressure_example() {
+        assert_near(saturation_vapor_pressure(20.0), 23.33);
+    }
+
+    #[test]
+    fn dew_point_example() {
+        assert_near(dew_point(20.0, 50.0), 9.26);
+        assert_near(dew_point(20.0, 100.0), 20.0);
+        assert!(dew_point(20.0, 0.0).is_finite());
+    }
+
+    #[test]
+    fn absolute_humidity_example() {
+        assert_near(absolute_humidity(20.0, 50.0), 8.62);
+    }
+
+    #[test]
+    fn vapor_pressure_deficit_example() {
+        assert_near(vapor_pressure_deficit(20.0, 50.0), 1.17);
+    }
+
+    #[test]
+    fn heat_index_example() {
+        assert_near(heat_index(20.0, 50.0), 19.36);
+        assert_near(heat_index(32.2222, 70.0), 41.07);
+    }
+}
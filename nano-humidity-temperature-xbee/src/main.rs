@@ -6,6 +6,7 @@
 use arduino_hal::{delay_ms, I2c};
 use arduino_hal::prelude::*;
 use panic_halt as _;
+use payload::{Field, Header};
 use xbee;
 use crate::Command::{NoHoldHumidity, NoHoldTemperature};
 use crate::Error::{Htu21Error, I2cError, NotSensorValue};
@@ -51,31 +52,47 @@ fn main() -> ! {
     let mut prev_temperature: Option<f32> = None;
     let mut prev_humidity: Option<f32> = None;
     let mut cycles = 0 as u8;
+    let mut sequence = 0 as u16;
     loop {
-        match read_sensor_values(&mut i2c) {
-            Ok((data, temperature, humidity)) => {
+        let sensor_values = read_sensor_values(&mut i2c);
+        let update = match sensor_values {
+            Ok((temperature, humidity)) => {
                 let update_temperature = prev_temperature
                     .map_or(true, |prev| exceeds_delta(prev, temperature, 0.1));
                 let update_humidity = prev_humidity
                     .map_or(true, |prev| (prev - humidity) > 0.5 || (prev - humidity) < -0.5);
-                if cycles > 9 || update_temperature || update_humidity {
-                    cycles = 0;
+                update_temperature || update_humidity
+            }
+            // errors are only reported with the periodic update
+            Err(_) => false,
+        };
+        if cycles > 9 || update {
+            cycles = 0;
+            let flags = if sequence == 0 { payload::FLAG_RESTARTED } else { 0 };
+            let header = Header::new(payload::NODE_TYPE_HUMIDITY_TEMPERATURE, sequence, flags);
+            let mut data = [0x00 as u8; 16];
+            let length = match sensor_values {
+                Ok((temperature, humidity)) => {
                     prev_temperature = Some(temperature);
                     prev_humidity = Some(humidity);
-                    let packet = xbee::Packet::new(xbee::ApiIdentifier::TxReq,
-                                                   [0x00, 0x13, 0xA2, 0x00, 0x40, 0x64, 0x03, 0x75],
-                                                   &data);
-                    xbee_sleep.set_low();
-                    delay_ms(200);
-                    for byte in packet.iter() {
-                        serial.write_byte(byte);
-                    }
-                    serial.flush();
-                    delay_ms(200);
-                    xbee_sleep.set_high();
+                    payload::encode(&header, &[Field::temperature(temperature), Field::humidity(humidity)], &mut data)
                 }
+                Err(e) => payload::encode(&header, &[Field::ErrorCode(e.code())], &mut data),
+            };
+            let coordinator = xbee::Address::Long([0x00, 0x13, 0xA2, 0x00, 0x40, 0x64, 0x03, 0x75]);
+            let tx_request = length.ok()
+                .and_then(|length| xbee::TxRequest::new(0x00, coordinator, 0x00, &data[..length]).ok());
+            if let Some(tx_request) = tx_request {
+                sequence = next_sequence(sequence);
+                xbee_sleep.set_low();
+                delay_ms(200);
+                for byte in tx_request.as_slice() {
+                    serial.write_byte(*byte);
+                }
+                serial.flush();
+                delay_ms(200);
+                xbee_sleep.set_high();
             }
-            _ => (),
         }
         cycles += 1;
         delay_ms(10);
@@ -88,25 +105,24 @@ fn main() -> ! {
     }
 }
 
+/// Sequence number of the next packet; 0 is only sent first after a reset, so wrapping skips it.
+fn next_sequence(sequence: u16) -> u16 {
+    return if sequence == u16::MAX { 1 } else { sequence + 1 };
+}
+
 fn exceeds_delta(a: f32, b: f32, delta: f32) -> bool {
     let x = a - b;
     return x > delta || x < -delta;
 }
 
-fn read_sensor_values(i2c: &mut I2c) -> Result<([u8; 4], f32, f32), Error> {
-    let mut xbee_data = [0x00 as u8; 4];
-
+fn read_sensor_values(i2c: &mut I2c) -> Result<(f32, f32), Error> {
     let temperature_data = read_sensor_value(i2c, NoHoldTemperature)?;
-    xbee_data[0] = temperature_data[0];
-    xbee_data[1] = temperature_data[1];
     let temperature = htu21::parse_temperature(&temperature_data)?;
 
     let humidity_data = read_sensor_value(i2c, NoHoldHumidity)?;
-    xbee_data[2] = humidity_data[0];
-    xbee_data[3] = humidity_data[1];
     let humidity = htu21::parse_humidity(&humidity_data)?;
 
-    return Ok((xbee_data, temperature, humidity));
+    return Ok((temperature, humidity));
 }
 
 fn read_sensor_value(i2c: &mut I2c, cmd: Command) -> Result<[u8; 2], Error> {
@@ -145,6 +161,16 @@ enum Error {
     Htu21Error(htu21::Error),
 }
 
+impl Error {
+    fn code(&self) -> u8 {
+        match &self {
+            NotSensorValue => return payload::ERROR_SENSOR_CRC,
+            I2cError(_) => return payload::ERROR_SENSOR_I2C,
+            Htu21Error(_) => return payload::ERROR_SENSOR_STATUS,
+        }
+    }
+}
+
 impl From<arduino_hal::i2c::Error> for Error {
     fn from(value: arduino_hal::i2c::Error) -> Self {
         return I2cError(value);
@@ -0,0 +1,310 @@
+#![no_std]
+
+//! Versioned sensor payload sent by the nodes in the RF data of a Transmit Request.
+//!
+//! A payload starts with a header of version, node type, big endian sequence number and flags, followed by
+//! type-length-value fields. Decoders skip fields of unknown types, so new fields need no new version.
+//!
+//! ```text
+//! 01 01 002a 00   version 1, node type 1, sequence 42, no flags
+//! 01 02 02c0      temperature 7.04 °C
+//! 02 02 1189      humidity 44.89 %
+//! ```
+
+/** Version written by [`encode`], the only one [`decode`] accepts. */
+pub const VERSION: u8 = 1;
+/** Length of the header: version, node type, sequence number and flags. */
+pub const HEADER_LENGTH: usize = 5;
+
+/** Node measuring temperature and humidity with an HTU21. */
+pub const NODE_TYPE_HUMIDITY_TEMPERATURE: u8 = 0x01;
+
+/** Set in the first payload after the node reset. */
+pub const FLAG_RESTARTED: u8 = 0b0000_0001;
+
+/** The HTU21 value failed its CRC check. */
+pub const ERROR_SENSOR_CRC: u8 = 0x01;
+/** The HTU21 did not answer on I2C. */
+pub const ERROR_SENSOR_I2C: u8 = 0x02;
+/** The HTU21 returned a humidity for a temperature or vice versa. */
+pub const ERROR_SENSOR_STATUS: u8 = 0x03;
+/** The real time clock did not answer or was not set. */
+pub const ERROR_RTC: u8 = 0x04;
+
+const TEMPERATURE: u8 = 0x01;
+const HUMIDITY: u8 = 0x02;
+const BATTERY: u8 = 0x03;
+const TIMESTAMP: u8 = 0x04;
+const ERROR_CODE: u8 = 0x05;
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    /** The buffer is too small for the payload. */
+    BufferTooSmall,
+    /** The payload ends within the header or a field. */
+    Truncated,
+    UnsupportedVersion(u8),
+    /** A field of a known type has the wrong length. */
+    InvalidLength { kind: u8, length: u8 },
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Header {
+    pub version: u8,
+    pub node_type: u8,
+    /** Counts the payloads from 0 after a reset, wrapping from 65535 to 1. */
+    pub sequence: u16,
+    pub flags: u8,
+}
+
+impl Header {
+    /** Header of the current version. */
+    pub fn new(node_type: u8, sequence: u16, flags: u8) -> Header {
+        return Header { version: VERSION, node_type, sequence, flags };
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Field<'a> {
+    /** Temperature in hundredths of °C. */
+    Temperature(i16),
+    /** Relative humidity in hundredths of %. */
+    Humidity(u16),
+    /** Battery level in %. */
+    Battery(u8),
+    /** Unix time in seconds of the node's real time clock. */
+    Timestamp(u32),
+    /** One of the `ERROR_` codes, or a node specific one. */
+    ErrorCode(u8),
+    /** Field of a type unknown to this version of the crate. */
+    Unknown { kind: u8, value: &'a [u8] },
+}
+
+/** Scales to hundredths, rounded half away from zero once cast, which saturates at the bounds of the type. */
+fn hundredths(value: f32) -> f32 {
+    let scaled = value * 100.0;
+    return if scaled < 0.0 { scaled - 0.5 } else { scaled + 0.5 };
+}
+
+impl Field<'_> {
+    /** Temperature field of a value in °C. */
+    pub fn temperature(celsius: f32) -> Field<'static> {
+        return Field::Temperature(hundredths(celsius) as i16);
+    }
+
+    /** Humidity field of a value in %. */
+    pub fn humidity(percent: f32) -> Field<'static> {
+        return Field::Humidity(hundredths(percent) as u16);
+    }
+
+    fn kind(&self) -> u8 {
+        return match self {
+            Field::Temperature(_) => TEMPERATURE,
+            Field::Humidity(_) => HUMIDITY,
+            Field::Battery(_) => BATTERY,
+            Field::Timestamp(_) => TIMESTAMP,
+            Field::ErrorCode(_) => ERROR_CODE,
+            Field::Unknown { kind, .. } => *kind,
+        };
+    }
+
+    /** Writes the field, returning its length including type and length bytes. */
+    fn encode(&self, buffer: &mut [u8]) -> Result<usize, Error> {
+        let mut value = [0x00u8; 4];
+        let value: &[u8] = match self {
+            Field::Temperature(temperature) => {
+                value[..2].copy_from_slice(&temperature.to_be_bytes());
+                &value[..2]
+            }
+            Field::Humidity(humidity) => {
+                value[..2].copy_from_slice(&humidity.to_be_bytes());
+                &value[..2]
+            }
+            Field::Battery(battery) | Field::ErrorCode(battery) => {
+                value[0] = *battery;
+                &value[..1]
+            }
+            Field::Timestamp(timestamp) => {
+                value.copy_from_slice(&timestamp.to_be_bytes());
+                &value
+            }
+            Field::Unknown { value, .. } => value,
+        };
+        let length = 2 + value.len();
+        if value.len() > u8::MAX as usize || buffer.len() < length {
+            return Err(Error::BufferTooSmall);
+        }
+        buffer[0] = self.kind();
+        buffer[1] = value.len() as u8;
+        buffer[2..length].copy_from_slice(value);
+        return Ok(length);
+    }
+}
+
+/** Writes the header and the fields, returning the length of the payload. */
+pub fn encode(header: &Header, fields: &[Field], buffer: &mut [u8]) -> Result<usize, Error> {
+    if buffer.len() < HEADER_LENGTH {
+        return Err(Error::BufferTooSmall);
+    }
+    buffer[0] = header.version;
+    buffer[1] = header.node_type;
+    buffer[2..4].copy_from_slice(&header.sequence.to_be_bytes());
+    buffer[4] = header.flags;
+    let mut length = HEADER_LENGTH;
+    for field in fields {
+        length += field.encode(&mut buffer[length..])?;
+    }
+    return Ok(length);
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub struct Payload<'a> {
+    pub header: Header,
+    fields: &'a [u8],
+}
+
+impl<'a> Payload<'a> {
+    /** Fields in the order they were encoded. */
+    pub fn fields(&self) -> Fields<'a> {
+        return Fields { rest: self.fields };
+    }
+}
+
+/** Iterator over the fields of a decoded, hence valid, payload. */
+pub struct Fields<'a> {
+    rest: &'a [u8],
+}
+
+impl<'a> Iterator for Fields<'a> {
+    type Item = Field<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        let (field, rest) = parse_field(self.rest)?.ok()?;
+        self.rest = rest;
+        return Some(field);
+    }
+}
+
+/** Parses the field at the start of the data and returns it with the data after it, or `None` at the end. */
+fn parse_field(data: &[u8]) -> Option<Result<(Field<'_>, &[u8]), Error>> {
+    if data.is_empty() {
+        return None;
+    }
+    if data.len() < 2 || data.len() < 2 + data[1] as usize {
+        return Some(Err(Error::Truncated));
+    }
+    let (kind, length) = (data[0], data[1]);
+    let (value, rest) = data[2..].split_at(length as usize);
+    let field = match (kind, value) {
+        (TEMPERATURE, &[msb, lsb]) => Field::Temperature(i16::from_be_bytes([msb, lsb])),
+        (HUMIDITY, &[msb, lsb]) => Field::Humidity(u16::from_be_bytes([msb, lsb])),
+        (BATTERY, &[battery]) => Field::Battery(battery),
+        (TIMESTAMP, &[b0, b1, b2, b3]) => Field::Timestamp(u32::from_be_bytes([b0, b1, b2, b3])),
+        (ERROR_CODE, &[code]) => Field::ErrorCode(code),
+        (TEMPERATURE..=ERROR_CODE, _) => return Some(Err(Error::InvalidLength { kind, length })),
+        _ => Field::Unknown { kind, value },
+    };
+    return Some(Ok((field, rest)));
+}
+
+/** Decodes the header and checks that the fields are well-formed. */
+pub fn decode(data: &[u8]) -> Result<Payload<'_>, Error> {
+    if data.is_empty() {
+        return Err(Error::Truncated);
+    }
+    if data[0] != VERSION {
+        return Err(Error::UnsupportedVersion(data[0]));
+    }
+    if data.len() < HEADER_LENGTH {
+        return Err(Error::Truncated);
+    }
+    let header = Header {
+        version: data[0],
+        node_type: data[1],
+        sequence: u16::from_be_bytes([data[2], data[3]]),
+        flags: data[4],
+    };
+    let mut rest = &data[HEADER_LENGTH..];
+    while let Some(field) = parse_field(rest) {
+        rest = field?.1;
+    }
+    return Ok(Payload { header, fields: &data[HEADER_LENGTH..] });
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{decode, encode, Error, Field, Header, FLAG_RESTARTED, NODE_TYPE_HUMIDITY_TEMPERATURE};
+
+    const GOLDEN: [u8; 13] = [0x01, 0x01, 0x00, 0x2a, 0x00, 0x01, 0x02, 0x02, 0xc0, 0x02, 0x02, 0x11, 0x89];
+    const GOLDEN_ALL: [u8; 25] = [
+        0x01, 0x01, 0x00, 0x00, 0x01,
+        0x01, 0x02, 0xff, 0x38,
+        0x02, 0x02, 0x27, 0x10,
+        0x03, 0x01, 0x57,
+        0x04, 0x04, 0x64, 0x4a, 0x3b, 0x80,
+        0x05, 0x01, 0x02,
+    ];
+
+    #[test]
+    fn encode_golden() {
+        let header = Header::new(NODE_TYPE_HUMIDITY_TEMPERATURE, 42, 0);
+        let mut buffer = [0x00u8; 32];
+        let length = encode(&header, &[Field::temperature(7.0436172), Field::humidity(44.88806)], &mut buffer).unwrap();
+        assert_eq!(buffer[..length], GOLDEN);
+    }
+
+    #[test]
+    fn encode_golden_all_fields() {
+        let header = Header::new(NODE_TYPE_HUMIDITY_TEMPERATURE, 0, FLAG_RESTARTED);
+        let fields = [Field::temperature(-2.0), Field::humidity(100.0), Field::Battery(87),
+                      Field::Timestamp(1_682_586_496), Field::ErrorCode(0x02)];
+        let mut buffer = [0x00u8; 32];
+        let length = encode(&header, &fields, &mut buffer).unwrap();
+        assert_eq!(buffer[..length], GOLDEN_ALL);
+    }
+
+    #[test]
+    fn encode_buffer_too_small() {
+        let header = Header::new(NODE_TYPE_HUMIDITY_TEMPERATURE, 42, 0);
+        let mut buffer = [0x00u8; 12];
+        let actual = encode(&header, &[Field::temperature(7.04), Field::humidity(44.89)], &mut buffer);
+        assert_eq!(actual, Err(Error::BufferTooSmall));
+    }
+
+    #[test]
+    fn decode_golden() {
+        let payload = decode(&GOLDEN).unwrap();
+        assert_eq!(payload.header, Header { version: 1, node_type: 0x01, sequence: 42, flags: 0 });
+        let mut fields = payload.fields();
+        assert_eq!(fields.next(), Some(Field::Temperature(704)));
+        assert_eq!(fields.next(), Some(Field::Humidity(4489)));
+        assert_eq!(fields.next(), None);
+    }
+
+    #[test]
+    fn decode_golden_all_fields() {
+        let payload = decode(&GOLDEN_ALL).unwrap();
+        assert_eq!(payload.header.flags, FLAG_RESTARTED);
+        let expected = [Field::Temperature(-200), Field::Humidity(10000), Field::Battery(87),
+                        Field::Timestamp(1_682_586_496), Field::ErrorCode(0x02)];
+        assert!(payload.fields().eq(expected.iter().copied()));
+    }
+
+    #[test]
+    fn decode_unknown_field() {
+        let data = [0x01, 0x01, 0x00, 0x01, 0x00, 0x7f, 0x03, 0xaa, 0xbb, 0xcc, 0x03, 0x01, 0x57];
+        let expected = [Field::Unknown { kind: 0x7f, value: &[0xaa, 0xbb, 0xcc] }, Field::Battery(87)];
+        assert!(decode(&data).unwrap().fields().eq(expected.iter().copied()));
+    }
+
+    #[test]
+    fn decode_invalid() {
+        assert_eq!(decode(&[]), Err(Error::Truncated));
+        assert_eq!(decode(&[0x4e, 0x85, 0x68, 0x3a]), Err(Error::UnsupportedVersion(0x4e)));
+        assert_eq!(decode(&GOLDEN[..4]), Err(Error::Truncated));
+        assert_eq!(decode(&GOLDEN[..12]), Err(Error::Truncated));
+        assert_eq!(decode(&GOLDEN[..6]), Err(Error::Truncated));
+        let data = [0x01, 0x01, 0x00, 0x01, 0x00, 0x01, 0x01, 0x07];
+        assert_eq!(decode(&data), Err(Error::InvalidLength { kind: 0x01, length: 1 }));
+    }
+}
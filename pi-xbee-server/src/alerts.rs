@@ -0,0 +1,343 @@
+use std::collections::BTreeMap;
+use std::process::Stdio;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use hyper::{Body, Method, Request};
+use log::{info, warn};
+use rumqttc::AsyncClient;
+use serde::Serialize;
+use tokio::io::AsyncWriteExt;
+use tokio::sync::broadcast::{self, error::RecvError};
+use crate::config::{AlertRule, Comparison, Config, Metric, NotifierConfig, NotifierKind};
+use crate::https::{self, HttpsClient};
+use crate::metrics::ALERT_FIRING_GAUGE;
+use crate::mqtt;
+use crate::reading::Reading;
+use crate::state;
+
+/// How long a notifier may take before it counts as failed.
+const NOTIFY_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// How often pending alerts are checked for their hold duration between readings.
+const PENDING_INTERVAL: Duration = Duration::from_secs(1);
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    /// The condition does not hold.
+    Ok,
+    /// The condition holds, but not for the hold duration yet.
+    Pending,
+    Firing,
+}
+
+/// Status of an alert rule for one node.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct AlertState {
+    pub alert: String,
+    pub address: String,
+    pub node: String,
+    pub metric: Metric,
+    pub status: Status,
+    /// Latest value of the metric.
+    pub value: f64,
+    pub threshold: f64,
+    /// Unix time in seconds the status was entered.
+    pub since: f64,
+}
+
+/// Sent to the notifiers when an alert fires or resolves.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Notification {
+    pub alert: String,
+    pub address: String,
+    pub node: String,
+    pub metric: Metric,
+    /// `firing` or `resolved`.
+    pub status: &'static str,
+    pub value: f64,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    /// Unix time in seconds of the reading that changed the status.
+    pub timestamp: f64,
+}
+
+/// Sets the firing gauge of the alert and returns its notification.
+fn notification(rule: &AlertRule, state: &AlertState, status: &'static str, timestamp: f64) -> Notification {
+    ALERT_FIRING_GAUGE.with_label_values(&[&rule.name, &state.address, &state.node])
+        .set(if state.status == Status::Firing { 1.0 } else { 0.0 });
+    return Notification {
+        alert: rule.name.clone(),
+        address: state.address.clone(),
+        node: state.node.clone(),
+        metric: rule.metric,
+        status,
+        value: state.value,
+        comparison: rule.comparison,
+        threshold: rule.threshold,
+        timestamp,
+    };
+}
+
+fn value(metric: Metric, reading: &Reading) -> Option<f64> {
+    return match metric {
+        Metric::Temperature => Some(reading.temperature),
+        Metric::Humidity => Some(reading.humidity),
+        Metric::Rssi => Some(reading.rssi),
+        Metric::Battery => reading.battery,
+    };
+}
+
+/// Evaluates the alert rules on each reading, keeping the status per rule and node.
+pub struct Alerts {
+    rules: Vec<AlertRule>,
+    states: Mutex<BTreeMap<(usize, String), AlertState>>,
+}
+
+impl Alerts {
+    pub fn new(rules: Vec<AlertRule>) -> Alerts {
+        return Alerts { rules, states: Mutex::new(BTreeMap::new()) };
+    }
+
+    pub fn states(&self) -> Vec<AlertState> {
+        return self.states.lock().unwrap().values().cloned().collect();
+    }
+
+    /// Updates the status of the rules matching the reading and returns the indices of the rules
+    /// whose alert fired or resolved, with their notifications.
+    pub fn evaluate(&self, reading: &Reading) -> Vec<(usize, Notification)> {
+        let mut notifications = Vec::new();
+        let mut states = self.states.lock().unwrap();
+        for (i, rule) in self.rules.iter().enumerate() {
+            if rule.node.as_ref().is_some_and(|node| node != &reading.node && node != &reading.address) {
+                continue;
+            }
+            let value = match value(rule.metric, reading) {
+                Some(value) => value,
+                None => continue,
+            };
+            let (breached, recovered) = match rule.comparison {
+                Comparison::Above => (value > rule.threshold, value <= rule.threshold - rule.hysteresis),
+                Comparison::Below => (value < rule.threshold, value >= rule.threshold + rule.hysteresis),
+            };
+            let state = states.entry((i, reading.address.clone())).or_insert_with(|| AlertState {
+                alert: rule.name.clone(),
+                address: reading.address.clone(),
+                node: reading.node.clone(),
+                metric: rule.metric,
+                status: Status::Ok,
+                value,
+                threshold: rule.threshold,
+                since: reading.timestamp,
+            });
+            state.value = value;
+            let previous = state.status;
+            match state.status {
+                Status::Ok if breached => state.status = Status::Pending,
+                Status::Pending if !breached => state.status = Status::Ok,
+                Status::Firing if recovered => state.status = Status::Ok,
+                _ => (),
+            }
+            if state.status != previous {
+                state.since = reading.timestamp;
+            }
+            if state.status == Status::Pending && reading.timestamp - state.since >= rule.hold as f64 {
+                state.status = Status::Firing;
+                state.since = reading.timestamp;
+            }
+            let status = match (previous, state.status) {
+                (Status::Firing, Status::Ok) => "resolved",
+                (Status::Ok | Status::Pending, Status::Firing) => "firing",
+                _ => continue,
+            };
+            notifications.push((i, notification(rule, state, status, reading.timestamp)));
+        }
+        return notifications;
+    }
+
+    /// Fires the pending alerts whose hold duration passed by `now` without a reading, e.g. from a node that died
+    /// in the cold it alerts about, and returns their notifications.
+    pub fn fire_pending(&self, now: f64) -> Vec<(usize, Notification)> {
+        let mut notifications = Vec::new();
+        let mut states = self.states.lock().unwrap();
+        for ((i, _), state) in states.iter_mut() {
+            let rule = &self.rules[*i];
+            if state.status == Status::Pending && now - state.since >= rule.hold as f64 {
+                state.status = Status::Firing;
+                state.since = now;
+                notifications.push((*i, notification(rule, state, "firing", now)));
+            }
+        }
+        return notifications;
+    }
+}
+
+/// Sends notifications to the configured notifiers.
+struct Notifiers {
+    notifiers: Vec<NotifierConfig>,
+    http: HttpsClient,
+    mqtt: Option<AsyncClient>,
+    qos: u8,
+}
+
+impl Notifiers {
+    fn new(config: Arc<Config>) -> Notifiers {
+        let mqtt = config.notifiers.iter().any(|notifier| notifier.kind == NotifierKind::Mqtt)
+            .then(|| mqtt::client(config.clone(), &format!("{}-alerts", config.mqtt.client_id)).0);
+        return Notifiers { notifiers: config.notifiers.clone(), http: https::client(), mqtt, qos: config.mqtt.qos };
+    }
+
+    async fn notify(&self, rule: &AlertRule, notification: &Notification) {
+        let payload = serde_json::to_string(notification).expect("notification serializable");
+        let notifiers = self.notifiers.iter()
+            .filter(|notifier| rule.notify.as_ref().is_none_or(|names| names.contains(&notifier.name)));
+        for notifier in notifiers {
+            let sent = tokio::time::timeout(NOTIFY_TIMEOUT, self.send(notifier, payload.clone())).await
+                .unwrap_or_else(|_| Err(format!("timed out after {:?}", NOTIFY_TIMEOUT)));
+            if let Err(e) = sent {
+                warn!("alert notification failed; alert={} notifier={} error={}", rule.name, notifier.name, e);
+            }
+        }
+    }
+
+    async fn send(&self, notifier: &NotifierConfig, payload: String) -> Result<(), String> {
+        match notifier.kind {
+            NotifierKind::Webhook => {
+                let request = Request::builder().method(Method::POST).uri(&notifier.url)
+                    .header("Content-Type", "application/json")
+                    .body(Body::from(payload))
+                    .map_err(|e| e.to_string())?;
+                let response = self.http.request(request).await.map_err(|e| e.to_string())?;
+                if !response.status().is_success() {
+                    return Err(format!("status {}", response.status()));
+                }
+            }
+            NotifierKind::Command => {
+                let mut child = tokio::process::Command::new(&notifier.command[0])
+                    .args(&notifier.command[1..])
+                    .stdin(Stdio::piped())
+                    .spawn()
+                    .map_err(|e| e.to_string())?;
+                let mut stdin = child.stdin.take().expect("stdin piped");
+                stdin.write_all(payload.as_bytes()).await.map_err(|e| e.to_string())?;
+                drop(stdin);
+                let status = child.wait().await.map_err(|e| e.to_string())?;
+                if !status.success() {
+                    return Err(format!("command exited with {}", status));
+                }
+            }
+            NotifierKind::Mqtt => {
+                let client = self.mqtt.as_ref().expect("mqtt client of mqtt notifier");
+                client.publish(&notifier.topic, mqtt::qos(self.qos), false, payload).await.map_err(|e| e.to_string())?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Evaluates the rules on the received readings, and the pending alerts every second, and notifies when an alert
+/// fires or resolves.
+pub async fn run(config: Arc<Config>, mut receiver: broadcast::Receiver<Reading>, alerts: Arc<Alerts>) {
+    let notifiers = Arc::new(Notifiers::new(config.clone()));
+    let mut interval = tokio::time::interval(PENDING_INTERVAL);
+    loop {
+        let notifications = tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(reading) => alerts.evaluate(&reading),
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("alerts lagging; missed={}", missed);
+                    continue;
+                }
+                Err(RecvError::Closed) => return,
+            },
+            _ = interval.tick() => alerts.fire_pending(state::unix_seconds(SystemTime::now())),
+        };
+        for (i, notification) in notifications {
+            info!("alert {}; alert={} address={} node={} value={} threshold={}", notification.status,
+                notification.alert, notification.address, notification.node, notification.value, notification.threshold);
+            let notifiers = notifiers.clone();
+            let rule = alerts.rules[i].clone();
+            tokio::spawn(async move { notifiers.notify(&rule, &notification).await });
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+    use super::*;
+
+    fn rule() -> AlertRule {
+        return AlertRule {
+            name: "cellar humid".to_string(),
+            metric: Metric::Humidity,
+            node: Some("cellar".to_string()),
+            comparison: Comparison::Above,
+            threshold: 70.0,
+            hysteresis: 2.0,
+            hold: 60,
+            notify: None,
+        };
+    }
+
+    fn reading(node: &str, timestamp: f64, humidity: f64) -> Reading {
+        let mut reading = Reading::example("abcd", node, SystemTime::UNIX_EPOCH);
+        reading.timestamp = timestamp;
+        reading.humidity = humidity;
+        return reading;
+    }
+
+    fn statuses(alerts: &Alerts, readings: &[(f64, f64)]) -> Vec<&'static str> {
+        return readings.iter()
+            .flat_map(|&(timestamp, humidity)| alerts.evaluate(&reading("cellar", timestamp, humidity)))
+            .map(|(_, notification)| notification.status)
+            .collect();
+    }
+
+    #[test]
+    fn hold_and_hysteresis() {
+        let alerts = Alerts::new(vec![rule()]);
+        assert!(statuses(&alerts, &[(0.0, 71.0), (30.0, 69.0), (40.0, 72.0), (90.0, 72.0)]).is_empty());
+        assert_eq!(alerts.states()[0].status, Status::Pending);
+        assert_eq!(statuses(&alerts, &[(100.0, 75.0)]), ["firing"]);
+        assert_eq!(alerts.states()[0].status, Status::Firing);
+        assert!(statuses(&alerts, &[(110.0, 69.0), (120.0, 71.0)]).is_empty());
+        assert_eq!(statuses(&alerts, &[(130.0, 68.0)]), ["resolved"]);
+        assert_eq!(alerts.states()[0].since, 130.0);
+        assert!(alerts.evaluate(&reading("attic", 140.0, 99.0)).is_empty());
+    }
+
+    #[test]
+    fn fire_pending() {
+        let alerts = Alerts::new(vec![rule()]);
+        assert!(statuses(&alerts, &[(0.0, 71.0)]).is_empty());
+        assert!(alerts.fire_pending(59.0).is_empty());
+        let fired = alerts.fire_pending(60.0);
+        assert_eq!(fired.len(), 1);
+        assert_eq!((fired[0].1.status, fired[0].1.value, fired[0].1.timestamp), ("firing", 71.0, 60.0));
+        assert_eq!(alerts.states()[0].status, Status::Firing);
+        assert!(alerts.fire_pending(120.0).is_empty());
+    }
+
+    #[tokio::test]
+    async fn command_notifier() {
+        let path = std::env::temp_dir().join(format!("pi-xbee-server-alert-{}.json", std::process::id()));
+        let mut config = Config::default();
+        config.notifiers.push(NotifierConfig {
+            name: "file".to_string(),
+            kind: NotifierKind::Command,
+            url: String::new(),
+            command: vec!["sh".to_string(), "-c".to_string(), format!("cat > {}", path.display())],
+            topic: String::new(),
+        });
+        let rule = AlertRule { hold: 0, ..rule() };
+        let alerts = Alerts::new(vec![rule.clone()]);
+        let (_, notification) = alerts.evaluate(&reading("cellar", 1.0, 80.0)).remove(0);
+        Notifiers::new(Arc::new(config)).notify(&rule, &notification).await;
+        let actual = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(path).unwrap();
+        assert_eq!(actual, concat!(
+            r#"{"alert":"cellar humid","address":"abcd","node":"cellar","metric":"humidity","status":"firing","#,
+            r#""value":80.0,"comparison":"above","threshold":70.0,"timestamp":1.0}"#));
+    }
+}
@@ -0,0 +1,334 @@
+use std::convert::Infallible;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use serde::{Deserialize, Serialize};
+use warp::{Filter, Rejection, Reply, http};
+use crate::alerts::Alerts;
+use crate::auth::{self, Auth};
+use crate::command::{self, Command};
+use crate::config::{CommandsConfig, Scope};
+use crate::history::{self, History, Point};
+use crate::reading::Reading;
+use crate::state::{self, State};
+use crate::storage::{Range, Storage};
+
+#[derive(Serialize, Debug)]
+pub struct NodeSummary {
+    /// Whether the node was found stale and has not sent a reading since.
+    pub stale: bool,
+    /// Latest reading of the node.
+    #[serde(flatten)]
+    pub reading: Reading,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct ReadingsQuery {
+    /// Number of most recent readings to return, all matching readings if omitted.
+    pub limit: Option<usize>,
+    /// Unix time in seconds of the oldest reading to return.
+    pub from: Option<f64>,
+    /// Unix time in seconds of the newest reading to return.
+    pub to: Option<f64>,
+}
+
+#[derive(Deserialize, Debug, Default)]
+pub struct HistoryQuery {
+    /// Unix time in seconds, a day before `to` if omitted.
+    pub from: Option<u64>,
+    /// Unix time in seconds, now if omitted.
+    pub to: Option<u64>,
+    /// Seconds per point, rounded up to a multiple of the history resolution.
+    pub step: Option<u64>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct HistoryReply {
+    pub address: String,
+    pub from: u64,
+    pub to: u64,
+    pub step: u64,
+    pub points: Vec<Point>,
+}
+
+#[derive(Serialize, Debug)]
+struct ErrorBody {
+    error: String,
+}
+
+pub fn error(status: http::StatusCode, error: String) -> warp::reply::WithStatus<warp::reply::Json> {
+    return warp::reply::with_status(warp::reply::json(&ErrorBody { error }), status);
+}
+
+pub fn nodes(state: &State) -> Vec<NodeSummary> {
+    return state.nodes().into_values()
+        .map(|node| NodeSummary { stale: node.stale, reading: node.reading })
+        .collect();
+}
+
+/// Returns the most recent readings of the node kept in memory, oldest first, or `None` if the node is unknown.
+pub fn readings(state: &State, address: &str, query: &ReadingsQuery) -> Option<Vec<Reading>> {
+    let range = Range { from: query.from, to: query.to };
+    let mut readings = state.readings(address)?;
+    readings.retain(|reading| range.contains(reading.timestamp));
+    if let Some(limit) = query.limit {
+        readings.drain(..readings.len().saturating_sub(limit));
+    }
+    return Some(readings);
+}
+
+/// Answers from the database if the sqlite sink is enabled, from memory otherwise.
+async fn readings_reply(address: String, query: ReadingsQuery, state: Arc<State>,
+                        storage: Option<Arc<Mutex<Storage>>>) -> Result<impl Reply, Infallible> {
+    let address = address.to_lowercase();
+    let readings = match storage {
+        Some(storage) => {
+            let range = Range { from: query.from, to: query.to };
+            let stored_address = address.clone();
+            let known = state.readings(&address).is_some();
+            let stored = tokio::task::spawn_blocking(move || {
+                let storage = storage.lock().unwrap();
+                let readings = storage.readings(&stored_address, range, query.limit)?;
+                // A node with no reading in the range is still known if it has other stored readings.
+                let known = known || !readings.is_empty() || storage.contains(&stored_address)?;
+                return Ok::<_, rusqlite::Error>((readings, known));
+            }).await.expect("sqlite query panicked");
+            match stored {
+                Ok((readings, true)) => Some(readings),
+                Ok((_, false)) => None,
+                Err(e) => return Ok(error(http::StatusCode::INTERNAL_SERVER_ERROR, format!("sqlite query failed: {}", e))),
+            }
+        }
+        None => readings(&state, &address, &query),
+    };
+    return Ok(match readings {
+        Some(readings) => warp::reply::with_status(warp::reply::json(&readings), http::StatusCode::OK),
+        None => error(http::StatusCode::NOT_FOUND, format!("node {} unknown", address)),
+    });
+}
+
+fn history_reply(address: String, query: HistoryQuery, history: &History,
+                 now: SystemTime) -> warp::reply::WithStatus<warp::reply::Json> {
+    let address = address.to_lowercase();
+    let to = query.to.unwrap_or(state::unix_seconds(now) as u64);
+    let from = query.from.unwrap_or(to.saturating_sub(86_400));
+    let step = history.step(query.step.unwrap_or(0));
+    if from > to {
+        return error(http::StatusCode::BAD_REQUEST, format!("from {} is after to {}", from, to));
+    }
+    if (to - from) / step > history::MAX_POINTS {
+        return error(http::StatusCode::BAD_REQUEST,
+                     format!("step {} yields more than {} points", step, history::MAX_POINTS));
+    }
+    return match history.query(&address, from, to, step) {
+        Some(points) => {
+            let reply = HistoryReply { address, from, to, step, points };
+            warp::reply::with_status(warp::reply::json(&reply), http::StatusCode::OK)
+        }
+        None => error(http::StatusCode::NOT_FOUND, format!("node {} unknown", address)),
+    };
+}
+
+/// `GET /api/nodes/{address}/history?from=&to=&step=`.
+pub fn history(history: Arc<History>, auth: Arc<Auth>) -> impl Filter<Extract=(impl Reply,), Error=Rejection> + Clone {
+    return warp::path!("api" / "nodes" / String / "history")
+        .and(auth::read(auth))
+        .and(warp::query::<HistoryQuery>())
+        .map(move |address, query| history_reply(address, query, &history, SystemTime::now()));
+}
+
+/// `GET /api/alerts`: status of the alert rules per node.
+pub fn alerts(alerts: Arc<Alerts>, auth: Arc<Auth>) -> impl Filter<Extract=(impl Reply,), Error=Rejection> + Clone {
+    return warp::path!("api" / "alerts")
+        .and(auth::read(auth))
+        .map(move || warp::reply::json(&alerts.states()));
+}
+
+async fn command_reply(address: String, body: hyper::body::Bytes, state: Arc<State>,
+                       timeout: Duration) -> Result<impl Reply, Infallible> {
+    let data = match serde_json::from_slice::<Command>(&body) {
+        Ok(command) => command.encode(),
+        Err(e) => return Ok(error(http::StatusCode::BAD_REQUEST, format!("command invalid: {}", e))),
+    };
+    let data = match data {
+        Ok(data) => data,
+        Err(e) => return Ok(error(http::StatusCode::BAD_REQUEST, e.to_string())),
+    };
+    return Ok(match state.transmitter().send(&address, &data, timeout).await {
+        Ok(delivery) if delivery.status == "success" =>
+            warp::reply::with_status(warp::reply::json(&delivery), http::StatusCode::OK),
+        Ok(delivery) => warp::reply::with_status(warp::reply::json(&delivery), http::StatusCode::BAD_GATEWAY),
+        Err(e @ command::Error::Timeout) => error(http::StatusCode::GATEWAY_TIMEOUT, e.to_string()),
+        Err(e @ command::Error::Busy) => error(http::StatusCode::SERVICE_UNAVAILABLE, e.to_string()),
+        Err(e) => error(http::StatusCode::BAD_REQUEST, e.to_string()),
+    });
+}
+
+/// `POST /api/nodes/{address}/commands` with a JSON [`Command`], answered with its [`command::Delivery`]
+/// once the TX Status arrived; requires an admin credential.
+pub fn commands(state: Arc<State>, config: &CommandsConfig,
+                auth: Arc<Auth>) -> impl Filter<Extract=(impl Reply,), Error=Rejection> + Clone {
+    let timeout = Duration::from_secs(config.timeout);
+    return warp::path!("api" / "nodes" / String / "commands")
+        .and(warp::post())
+        .and(auth::require(auth, Scope::Admin, false))
+        .and(warp::body::content_length_limit(4_096))
+        .and(warp::body::bytes())
+        .and_then(move |address, body| {
+            return command_reply(address, body, state.clone(), timeout);
+        });
+}
+
+/// `GET /api/nodes` and `GET /api/nodes/{address}/readings?limit=&from=&to=`.
+pub fn routes(state: Arc<State>, storage: Option<Arc<Mutex<Storage>>>,
+              auth: Arc<Auth>) -> impl Filter<Extract=(impl Reply,), Error=Rejection> + Clone {
+    let nodes_state = state.clone();
+    let nodes = warp::path!("api" / "nodes")
+        .and(auth::read(auth.clone()))
+        .map(move || warp::reply::json(&nodes(&nodes_state)));
+
+    let readings = warp::path!("api" / "nodes" / String / "readings")
+        .and(auth::read(auth))
+        .and(warp::query::<ReadingsQuery>())
+        .and(warp::any().map(move || state.clone()))
+        .and(warp::any().map(move || storage.clone()))
+        .and_then(readings_reply);
+
+    return nodes.or(readings);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use crate::state;
+    use super::*;
+
+    fn open() -> Arc<Auth> {
+        return Arc::new(Auth::new(&crate::config::Config::default()));
+    }
+
+    fn state() -> Arc<State> {
+        let state = Arc::new(State::with_recent_readings(2));
+        for seconds in 0..3 {
+            let time = state.started + Duration::from_secs(seconds);
+            state.node_seen(&Reading::example("abcd", "garage", time), time);
+        }
+        return state;
+    }
+
+    #[tokio::test]
+    async fn nodes() {
+        let state = state();
+        let response = warp::test::request().path("/api/nodes").reply(&routes(state.clone(), None, open())).await;
+        assert_eq!(response.status(), 200);
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.starts_with(r#"[{"stale":false,"address":"abcd","node":"garage","timestamp":"#), "{}", body);
+        assert!(body.contains(r#""payload":"68dc2b4e8560""#), "{}", body);
+    }
+
+    #[tokio::test]
+    async fn readings() {
+        let state = state();
+        let expected = state.started + Duration::from_secs(2);
+        let query = ReadingsQuery { limit: Some(1), ..ReadingsQuery::default() };
+        let actual = super::readings(&state, "abcd", &query).unwrap();
+        assert_eq!(actual, [Reading::example("abcd", "garage", expected)]);
+        let query = ReadingsQuery { to: Some(state::unix_seconds(expected) - 0.5), ..ReadingsQuery::default() };
+        assert_eq!(super::readings(&state, "abcd", &query).unwrap().len(), 1);
+
+        let response = warp::test::request().path("/api/nodes/ABCD/readings?limit=1")
+            .reply(&routes(state.clone(), None, open())).await;
+        assert_eq!(response.status(), 200);
+        let response = warp::test::request().path("/api/nodes/1234/readings").reply(&routes(state, None, open())).await;
+        assert_eq!(response.status(), 404);
+        assert_eq!(response.body().as_ref(), br#"{"error":"node 1234 unknown"}"#);
+    }
+
+    #[tokio::test]
+    async fn history() {
+        let history = Arc::new(History::new(&crate::config::HistoryConfig::default()));
+        let mut reading = Reading::example("abcd", "garage", SystemTime::UNIX_EPOCH);
+        for timestamp in [60.0, 90.0, 150.0] {
+            reading.timestamp = timestamp;
+            history.record(&reading, SystemTime::UNIX_EPOCH);
+        }
+        let routes = super::history(history, open());
+        let response = warp::test::request().path("/api/nodes/abcd/history?from=0&to=200&step=100").reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.starts_with(r#"{"address":"abcd","from":0,"to":200,"step":120,"points":[{"start":0,"end":120,"count":2,"#), "{}", body);
+        let response = warp::test::request().path("/api/nodes/abcd/history?from=0&to=3000000&step=60").reply(&routes).await;
+        assert_eq!(response.status(), 400);
+        let response = warp::test::request().path("/api/nodes/1234/history").reply(&routes).await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn protected() {
+        let mut config = crate::config::Config::default();
+        config.auth.enabled = true;
+        config.auth.credentials.push(crate::config::Credential {
+            scope: Scope::Read,
+            token: Some("reader".to_string()),
+            username: None,
+            password: None,
+        });
+        let routes = routes(state(), None, Arc::new(Auth::new(&config))).recover(auth::recover);
+        let response = warp::test::request().path("/api/nodes").reply(&routes).await;
+        assert_eq!(response.status(), 401);
+        let response = warp::test::request().path("/api/nodes").header("authorization", "Bearer reader")
+            .reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        let response = warp::test::request().path("/nope").reply(&routes).await;
+        assert_eq!(response.status(), 404);
+    }
+
+    #[tokio::test]
+    async fn commands() {
+        let state = Arc::new(State::new());
+        let config = crate::config::Config {
+            commands: CommandsConfig { enabled: true, token: Some("secret".to_string()), timeout: 5 },
+            ..Default::default()
+        };
+        let auth = Arc::new(Auth::new(&config));
+        let routes = super::commands(state.clone(), &config.commands, auth).recover(auth::recover);
+        let request = || warp::test::request().method("POST").path("/api/nodes/5678/commands");
+        let response = request().body(r#"{"command":"force_reading"}"#).reply(&routes).await;
+        assert_eq!(response.status(), 401);
+        let response = request().header("authorization", "Bearer secret").body(r#"{"command":"reboot"}"#)
+            .reply(&routes).await;
+        assert_eq!(response.status(), 400);
+
+        let delivered = state.clone();
+        tokio::spawn(async move {
+            while delivered.transmitter().take_queued().is_empty() {
+                tokio::task::yield_now().await;
+            }
+            delivered.transmitter().status_received(&xbee::TxStatus { frame_id: 2, status: xbee::DeliveryStatus::Success });
+        });
+        let response = request().header("authorization", "Bearer secret")
+            .body(r#"{"command":"set_report_interval","seconds":300}"#).reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body().as_ref(), br#"{"address":"5678","frame_id":2,"data":"02012c","status":"success"}"#);
+    }
+
+    #[tokio::test]
+    async fn stored_readings() {
+        let storage = Storage::open_in_memory().unwrap();
+        let mut reading = Reading::example("abcd", "garage", state().started);
+        for timestamp in [100.0, 200.0, 300.0] {
+            reading.timestamp = timestamp;
+            storage.insert(&reading).unwrap();
+        }
+        let routes = routes(Arc::new(State::new()), Some(Arc::new(Mutex::new(storage))), open());
+        let response = warp::test::request().path("/api/nodes/abcd/readings?from=150&to=300&limit=10").reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        let body = String::from_utf8(response.body().to_vec()).unwrap();
+        assert!(body.starts_with(r#"[{"address":"abcd","node":"garage","timestamp":200.0,"#), "{}", body);
+        assert_eq!(body.matches("timestamp").count(), 2);
+        let response = warp::test::request().path("/api/nodes/abcd/readings?from=400").reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.body().as_ref(), b"[]");
+        let response = warp::test::request().path("/api/nodes/1234/readings").reply(&routes).await;
+        assert_eq!(response.status(), 404);
+    }
+}
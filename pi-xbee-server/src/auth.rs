@@ -0,0 +1,168 @@
+use std::sync::Arc;
+use base64::Engine;
+use base64::engine::general_purpose::STANDARD;
+use warp::{Filter, Rejection, Reply, http};
+use warp::http::header::{HeaderValue, WWW_AUTHENTICATE};
+use crate::api;
+use crate::config::{Config, Credential, Scope};
+
+/// Credentials accepted by the HTTP endpoints, as expected `Authorization` header values.
+pub struct Auth {
+    /// Whether [`read`] requires credentials.
+    enabled: bool,
+    credentials: Vec<(String, Scope)>,
+    /// Challenge sent with 401 responses, Basic if a credential has a password so browsers prompt for it.
+    challenge: &'static str,
+}
+
+#[derive(Debug)]
+pub enum Denied {
+    /// No or unknown credentials.
+    Unauthorized(&'static str),
+    /// Valid credentials lacking the scope.
+    Forbidden,
+}
+
+impl warp::reject::Reject for Denied {}
+
+/// Returns the `Authorization` header value the credential is accepted as.
+pub fn authorization(credential: &Credential) -> String {
+    return match (&credential.token, &credential.username, &credential.password) {
+        (Some(token), _, _) => format!("Bearer {}", token),
+        (None, username, password) => {
+            let username = username.as_deref().unwrap_or_default();
+            let password = password.as_deref().unwrap_or_default();
+            format!("Basic {}", STANDARD.encode(format!("{}:{}", username, password)))
+        }
+    };
+}
+
+/// Returns the `Authorization` header value of `commands.token`, or of the first admin credential.
+pub fn admin_authorization(config: &Config) -> Option<String> {
+    if let Some(token) = config.commands.token.as_ref().filter(|token| !token.is_empty()) {
+        return Some(format!("Bearer {}", token));
+    }
+    return config.auth.credentials.iter()
+        .find(|credential| credential.scope == Scope::Admin)
+        .map(authorization);
+}
+
+/// Compares the authorization header with the expected one in constant time.
+fn equal(authorization: &str, expected: &str) -> bool {
+    return authorization.len() == expected.len()
+        && authorization.bytes().zip(expected.bytes()).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0;
+}
+
+impl Auth {
+    /// The configured credentials, with `commands.token` as an admin bearer token.
+    pub fn new(config: &Config) -> Auth {
+        let mut credentials: Vec<_> = config.auth.credentials.iter()
+            .map(|credential| (authorization(credential), credential.scope))
+            .collect();
+        if let Some(token) = config.commands.token.as_ref().filter(|token| !token.is_empty()) {
+            credentials.push((format!("Bearer {}", token), Scope::Admin));
+        }
+        let basic = config.auth.credentials.iter().any(|credential| credential.password.is_some());
+        let challenge = if basic { r#"Basic realm="pi-xbee-server""# } else { r#"Bearer realm="pi-xbee-server""# };
+        return Auth { enabled: config.auth.enabled, credentials, challenge };
+    }
+
+    /// Returns the highest scope granted by the header, checking every credential so the time taken does not
+    /// depend on which one matched.
+    pub fn scope(&self, authorization: Option<&str>) -> Option<Scope> {
+        let authorization = authorization?;
+        return self.credentials.iter()
+            .filter(|(expected, _)| equal(authorization, expected))
+            .map(|(_, scope)| *scope)
+            .fold(None, |granted, scope| granted.max(Some(scope)));
+    }
+}
+
+/// Passes requests if `open` or if their `Authorization` header grants the scope, rejects them with [`Denied`]
+/// otherwise.
+pub fn require(auth: Arc<Auth>, scope: Scope, open: bool) -> impl Filter<Extract=(), Error=Rejection> + Clone {
+    return warp::header::optional::<String>("authorization")
+        .and_then(move |authorization: Option<String>| {
+            let auth = auth.clone();
+            async move {
+                if open {
+                    return Ok(());
+                }
+                return match auth.scope(authorization.as_deref()) {
+                    Some(granted) if granted >= scope => Ok(()),
+                    Some(_) => Err(warp::reject::custom(Denied::Forbidden)),
+                    None => Err(warp::reject::custom(Denied::Unauthorized(auth.challenge))),
+                };
+            }
+        })
+        .untuple_one();
+}
+
+/// [`require`] of the read scope, open unless `auth.enabled`; goes after the path of the route, so unknown paths
+/// still answer 404.
+pub fn read(auth: Arc<Auth>) -> impl Filter<Extract=(), Error=Rejection> + Clone {
+    let open = !auth.enabled;
+    return require(auth, Scope::Read, open);
+}
+
+/// Answers [`Denied`] rejections with 401 or 403 and passes on the others.
+pub async fn recover(rejection: Rejection) -> Result<impl Reply, Rejection> {
+    let response = match rejection.find::<Denied>() {
+        Some(Denied::Unauthorized(challenge)) => {
+            let error = api::error(http::StatusCode::UNAUTHORIZED, "authorization missing or invalid".to_string());
+            let mut response = error.into_response();
+            response.headers_mut().insert(WWW_AUTHENTICATE, HeaderValue::from_static(challenge));
+            response
+        }
+        Some(Denied::Forbidden) =>
+            api::error(http::StatusCode::FORBIDDEN, "authorization lacks the admin scope".to_string()).into_response(),
+        None => return Err(rejection),
+    };
+    return Ok(response);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn config() -> Config {
+        let mut config = Config::default();
+        config.auth.credentials = vec![
+            Credential { scope: Scope::Read, token: Some("reader".to_string()), username: None, password: None },
+            Credential {
+                scope: Scope::Admin,
+                token: None,
+                username: Some("admin".to_string()),
+                password: Some("secret".to_string()),
+            },
+        ];
+        return config;
+    }
+
+    #[test]
+    fn scopes() {
+        let auth = Auth::new(&config());
+        assert_eq!(auth.scope(Some("Bearer reader")), Some(Scope::Read));
+        assert_eq!(auth.scope(Some("Basic YWRtaW46c2VjcmV0")), Some(Scope::Admin));
+        assert_eq!(auth.scope(Some("Bearer readers")), None);
+        assert_eq!(auth.scope(None), None);
+        assert_eq!(admin_authorization(&config()).unwrap(), "Basic YWRtaW46c2VjcmV0");
+    }
+
+    #[tokio::test]
+    async fn require() {
+        let auth = Arc::new(Auth::new(&config()));
+        let routes = super::require(auth.clone(), Scope::Admin, false).map(warp::reply)
+            .or(super::require(auth, Scope::Read, true).and(warp::path!("open")).map(warp::reply))
+            .recover(recover);
+        let response = warp::test::request().reply(&routes).await;
+        assert_eq!(response.status(), 401);
+        assert_eq!(response.headers()["www-authenticate"], r#"Basic realm="pi-xbee-server""#);
+        let response = warp::test::request().header("authorization", "Bearer reader").reply(&routes).await;
+        assert_eq!(response.status(), 403);
+        let response = warp::test::request().header("authorization", "Basic YWRtaW46c2VjcmV0").reply(&routes).await;
+        assert_eq!(response.status(), 200);
+        let response = warp::test::request().path("/open").reply(&routes).await;
+        assert_eq!(response.status(), 200);
+    }
+}
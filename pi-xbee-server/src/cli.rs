@@ -0,0 +1,218 @@
+use std::fs;
+use std::io::Write;
+use std::path::Path;
+use std::time::{Duration, Instant, SystemTime};
+use xbee::ApiIdentifier;
+use crate::command::{self, Command};
+use crate::config::{Config, SendArgs};
+use crate::decode;
+use crate::frame;
+use crate::state;
+use crate::transport;
+
+/// Frame id of the requests of `send`, a non-zero id requests a response.
+const FRAME_ID: u8 = 0x01;
+
+/// `decode <hex>`: prints the fields of the frame.
+pub fn decode(config: &Config, frame: &[String]) -> i32 {
+    let frame = frame.join("");
+    return match decode::parse_hex(&frame) {
+        Some(buffer) => {
+            println!("{}", decode::pretty(&decode::fields(&buffer, config)));
+            0
+        }
+        None => {
+            eprintln!("frame {:?} is not hex", frame);
+            2
+        }
+    };
+}
+
+/// `listen`: prints every frame received on the transport until it fails.
+pub fn listen(config: &Config) -> i32 {
+    let mut transport = match transport::open(&config.serial) {
+        Ok(transport) => transport,
+        Err(e) => {
+            eprintln!("transport unable to open; transport={:?} error={}", config.serial.transport, e);
+            return 1;
+        }
+    };
+    loop {
+        match frame::read_frame(&mut transport) {
+            Ok(Some(buffer)) => {
+                let timestamp = state::unix_seconds(SystemTime::now());
+                println!("timestamp={:.3} {}", timestamp, decode::line(&decode::fields(&buffer, config)));
+            }
+            Ok(None) => (),
+            Err(e) => {
+                eprintln!("transport failed; error={}", e);
+                return 1;
+            }
+        }
+    }
+}
+
+/// `replay <capture>`: prints every frame of the capture.
+pub fn replay(config: &Config, capture: &Path) -> i32 {
+    let content = match fs::read(capture) {
+        Ok(content) => content,
+        Err(e) => {
+            eprintln!("capture unable to read; path={} error={}", capture.display(), e);
+            return 1;
+        }
+    };
+    let mut stdout = std::io::stdout().lock();
+    for buffer in capture_frames(&content) {
+        if writeln!(stdout, "{}", decode::line(&decode::fields(&buffer, config))).is_err() {
+            break;
+        }
+    }
+    return 0;
+}
+
+/// Splits a capture into frames: hex frames one per line, skipping blank and `#` lines, or else raw bytes.
+fn capture_frames(content: &[u8]) -> Vec<Vec<u8>> {
+    let lines = std::str::from_utf8(content).ok().and_then(|text| {
+        return text.lines()
+            .map(|line| line.trim())
+            .filter(|line| !line.is_empty() && !line.starts_with('#'))
+            .map(decode::parse_hex)
+            .collect::<Option<Vec<Vec<u8>>>>();
+    });
+    if let Some(frames) = lines {
+        return frames;
+    }
+    let mut stream = content;
+    let mut frames = Vec::new();
+    loop {
+        match frame::read_frame(&mut stream) {
+            Ok(Some(buffer)) => frames.push(buffer),
+            Ok(None) => (),
+            Err(_) => return frames,
+        }
+    }
+}
+
+/// `send`: transmits the command or AT command and prints the TX Status or AT Command Response.
+pub async fn send(config: &Config, args: &SendArgs) -> i32 {
+    if let Some(at) = &args.at {
+        let (command, parameter) = match parse_at(at) {
+            Some(at) => at,
+            None => {
+                eprintln!("at command {:?} is not of the form XY or XY=<hex> with at most {} bytes",
+                    at, xbee::MAX_AT_PARAMETER);
+                return 2;
+            }
+        };
+        let at_command = xbee::AtCommand::new(FRAME_ID, command, &parameter).expect("parameter length checked");
+        return transmit(config, at_command.as_slice(), ApiIdentifier::AtCommandResponse);
+    }
+    let address = args.node.as_deref().expect("node required without --at");
+    let command = match args.command.as_deref().expect("command required without --at").parse::<Command>() {
+        Ok(command) => command,
+        Err(e) => {
+            eprintln!("{}", e);
+            return 2;
+        }
+    };
+    if args.server {
+        return match command::post(config, address, &command).await {
+            Ok((delivered, body)) => {
+                println!("{}", body);
+                if delivered { 0 } else { 1 }
+            }
+            Err(e) => {
+                eprintln!("command unable to send; error={}", e);
+                1
+            }
+        };
+    }
+    let request = command.encode()
+        .and_then(|data| command::tx_request(FRAME_ID, command::parse_address(address)?, &data));
+    return match request {
+        Ok(tx_request) => transmit(config, tx_request.as_slice(), ApiIdentifier::TxStatus),
+        Err(e) => {
+            eprintln!("{}", e);
+            2
+        }
+    };
+}
+
+/// Parses `XY` or `XY=<hex>` into the command and its parameter.
+fn parse_at(value: &str) -> Option<([u8; 2], Vec<u8>)> {
+    let (command, parameter) = value.split_once('=').unwrap_or((value, ""));
+    let command: [u8; 2] = command.to_uppercase().as_bytes().try_into().ok()?;
+    let parameter = decode::parse_hex(parameter)?;
+    if parameter.len() > xbee::MAX_AT_PARAMETER {
+        return None;
+    }
+    return Some((command, parameter));
+}
+
+/// Writes the request to the transport and prints frames until the response to it arrived.
+fn transmit(config: &Config, request: &[u8], response: ApiIdentifier) -> i32 {
+    let mut transport = match transport::open(&config.serial) {
+        Ok(transport) => transport,
+        Err(e) => {
+            eprintln!("transport unable to open; transport={:?} error={}", config.serial.transport, e);
+            return 1;
+        }
+    };
+    println!("{}", decode::line(&decode::fields(request, config)));
+    if let Err(e) = transport.write_all(request).and_then(|_| transport.flush()) {
+        eprintln!("transport failed; error={}", e);
+        return 1;
+    }
+    let deadline = Instant::now() + Duration::from_secs(config.commands.timeout);
+    while Instant::now() < deadline {
+        let buffer = match frame::read_frame(&mut transport) {
+            Ok(Some(buffer)) => buffer,
+            Ok(None) => continue,
+            Err(e) => {
+                eprintln!("transport failed; error={}", e);
+                return 1;
+            }
+        };
+        let fields = decode::fields(&buffer, config);
+        let answered = xbee::api_identifier(&buffer) == Ok(response) && buffer[xbee::HEADER_LENGTH + 1] == FRAME_ID;
+        if !answered {
+            println!("{}", decode::line(&fields));
+            continue;
+        }
+        println!("{}", decode::pretty(&fields));
+        let succeeded = fields.iter().any(|(name, value)| *name == "status" && (value == "success" || value == "ok"));
+        return if succeeded { 0 } else { 1 };
+    }
+    eprintln!("no response received within {} seconds", config.commands.timeout);
+    return 1;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const FRAME: [u8; 13] = [0x7e, 0x00, 0x09, 0x81, 0x56, 0x78, 0x28, 0x00, 0x4e, 0x85, 0x68, 0x3a, 0x13];
+
+    #[test]
+    fn text_capture() {
+        let capture = b"# rx16\n7e000981567828004e85683a13\n\n7E 00 03 89 02 01 73\n";
+        assert_eq!(capture_frames(capture), [FRAME.to_vec(), vec![0x7e, 0x00, 0x03, 0x89, 0x02, 0x01, 0x73]]);
+    }
+
+    #[test]
+    fn raw_capture() {
+        let mut capture = vec![0x00, 0x13];
+        capture.extend_from_slice(&FRAME);
+        capture.extend_from_slice(&FRAME);
+        capture.extend_from_slice(&FRAME[..4]);
+        assert_eq!(capture_frames(&capture), [FRAME.to_vec(), FRAME.to_vec()]);
+    }
+
+    #[test]
+    fn at() {
+        assert_eq!(parse_at("db"), Some((*b"DB", vec![])));
+        assert_eq!(parse_at("NJ=ff"), Some((*b"NJ", vec![0xff])));
+        assert_eq!(parse_at("NJ=f"), None);
+        assert_eq!(parse_at("DBX"), None);
+    }
+}
@@ -0,0 +1,357 @@
+use std::collections::{HashMap, VecDeque};
+use std::fmt;
+use std::str::FromStr;
+use std::sync::Mutex;
+use std::time::Duration;
+use hex_string::HexString;
+use hyper::{Body, Client, Method, Request};
+use hyper::header::{AUTHORIZATION, CONTENT_TYPE};
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use tokio::sync::oneshot;
+use xbee::{Address, DeliveryStatus, TxRequest, TxStatus};
+use crate::auth;
+use crate::config::Config;
+use crate::metrics::COMMANDS_COUNTER;
+
+/// First frame id handed out to commands; 0 suppresses the TX Status and the reader's `DB` queries use 1.
+const FIRST_FRAME_ID: u8 = 2;
+
+const FORCE_READING: u8 = 0x01;
+const SET_REPORT_INTERVAL: u8 = 0x02;
+const SET_DELTAS: u8 = 0x03;
+
+/// Application command of the sensor node firmware, transmitted as the RF data of a TX request.
+///
+/// Encoded as an opcode byte followed by big endian arguments; `raw` sends the given hex data as is.
+#[derive(Serialize, Deserialize, Debug, PartialEq, Clone)]
+#[serde(tag = "command", rename_all = "snake_case", deny_unknown_fields)]
+pub enum Command {
+    /// Read and report the sensor values now.
+    ForceReading,
+    /// Report at least every `seconds`, even if the values did not change.
+    SetReportInterval { seconds: u16 },
+    /// Report as soon as a value moved by more than its delta, in celcius and percent.
+    SetDeltas { temperature: f64, humidity: f64 },
+    Raw { data: String },
+}
+
+#[derive(Debug, PartialEq)]
+pub enum Error {
+    InvalidCommand(String),
+    InvalidAddress(String),
+    InvalidData(String),
+    DataTooLong(usize),
+    InvalidDelta(f64),
+    /// Every frame id is waiting for a TX Status.
+    Busy,
+    /// No TX Status arrived within the timeout.
+    Timeout,
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::InvalidCommand(command) => write!(f, "command {:?} is not one of force_reading, \
+                set_report_interval=<seconds>, set_deltas=<temperature>,<humidity> or raw=<hex>", command),
+            Error::InvalidAddress(address) =>
+                write!(f, "address {:?} is not a 4 or 16 digit hex address", address),
+            Error::InvalidData(data) => write!(f, "data {:?} is not hex", data),
+            Error::DataTooLong(length) =>
+                write!(f, "data of {} bytes exceeds the {} bytes of a tx request", length, xbee::MAX_TX_DATA),
+            Error::InvalidDelta(delta) => write!(f, "delta {} is not within 0..=655.35", delta),
+            Error::Busy => write!(f, "too many commands waiting for their tx status"),
+            Error::Timeout => write!(f, "no tx status received"),
+        }
+    }
+}
+
+impl FromStr for Command {
+    type Err = Error;
+
+    /// Parses the command line form, e.g. `force_reading`, `set_report_interval=300`, `set_deltas=0.1,0.5`
+    /// or `raw=0102`.
+    fn from_str(value: &str) -> Result<Command, Error> {
+        let invalid = || Error::InvalidCommand(value.to_string());
+        let (name, argument) = match value.split_once('=') {
+            Some((name, argument)) => (name, Some(argument)),
+            None => (value, None),
+        };
+        return match (name, argument) {
+            ("force_reading", None) => Ok(Command::ForceReading),
+            ("set_report_interval", Some(seconds)) =>
+                Ok(Command::SetReportInterval { seconds: seconds.parse().map_err(|_| invalid())? }),
+            ("set_deltas", Some(deltas)) => {
+                let (temperature, humidity) = deltas.split_once(',').ok_or_else(invalid)?;
+                Ok(Command::SetDeltas {
+                    temperature: temperature.parse().map_err(|_| invalid())?,
+                    humidity: humidity.parse().map_err(|_| invalid())?,
+                })
+            }
+            ("raw", Some(data)) => Ok(Command::Raw { data: data.to_string() }),
+            _ => Err(invalid()),
+        };
+    }
+}
+
+impl Command {
+    /// Returns the RF data of the command.
+    pub fn encode(&self) -> Result<Vec<u8>, Error> {
+        let data = match self {
+            Command::ForceReading => vec![FORCE_READING],
+            Command::SetReportInterval { seconds } => {
+                let mut data = vec![SET_REPORT_INTERVAL];
+                data.extend_from_slice(&seconds.to_be_bytes());
+                data
+            }
+            Command::SetDeltas { temperature, humidity } => {
+                let mut data = vec![SET_DELTAS];
+                data.extend_from_slice(&hundredths(*temperature)?.to_be_bytes());
+                data.extend_from_slice(&hundredths(*humidity)?.to_be_bytes());
+                data
+            }
+            Command::Raw { data } => HexString::from_string(&data.to_lowercase())
+                .map_err(|_| Error::InvalidData(data.clone()))?
+                .as_bytes(),
+        };
+        return Ok(data);
+    }
+}
+
+fn hundredths(delta: f64) -> Result<u16, Error> {
+    let value = (delta * 100.0).round();
+    if !(0.0..=u16::MAX as f64).contains(&value) {
+        return Err(Error::InvalidDelta(delta));
+    }
+    return Ok(value as u16);
+}
+
+/// Parses a 4 (16-bit) or 16 (64-bit) digit hex node address.
+pub fn parse_address(address: &str) -> Result<Address, Error> {
+    let invalid = || Error::InvalidAddress(address.to_string());
+    if address.len() != 4 && address.len() != 16 {
+        return Err(invalid());
+    }
+    let bytes = HexString::from_string(&address.to_lowercase()).map_err(|_| invalid())?.as_bytes();
+    return Ok(match bytes.len() {
+        2 => Address::Short([bytes[0], bytes[1]]),
+        _ => {
+            let mut long = [0x00u8; 8];
+            long.copy_from_slice(&bytes);
+            Address::Long(long)
+        }
+    });
+}
+
+/// Builds the TX request carrying the data to the destination.
+pub fn tx_request(frame_id: u8, destination: Address, data: &[u8]) -> Result<TxRequest, Error> {
+    return TxRequest::new(frame_id, destination, 0x00, data).map_err(|e| match e {
+        xbee::Error::DataTooLong(length) => Error::DataTooLong(length),
+        e => unreachable!("tx request failed; error={:?}", e),
+    });
+}
+
+/// Value of the `status` label and field of a delivery status.
+pub fn delivery_status_label(status: DeliveryStatus) -> &'static str {
+    return match status {
+        DeliveryStatus::Success => "success",
+        DeliveryStatus::NoAck => "no_ack",
+        DeliveryStatus::CcaFailure => "cca_failure",
+        DeliveryStatus::Purged => "purged",
+        DeliveryStatus::Other(_) => "other",
+    };
+}
+
+/// Result of a command as reported by the coordinator's TX Status.
+#[derive(Serialize, Debug, PartialEq)]
+pub struct Delivery {
+    pub address: String,
+    pub frame_id: u8,
+    /// RF data sent, as hex.
+    pub data: String,
+    pub status: &'static str,
+}
+
+/// Hands TX requests to the reader, which owns the transport, and their TX Status back to the sender.
+pub struct Transmitter {
+    /// TX request frames waiting to be written, with their frame id.
+    queue: Mutex<VecDeque<(u8, Vec<u8>)>>,
+    /// Senders waiting for a TX Status by frame id.
+    pending: Mutex<HashMap<u8, oneshot::Sender<DeliveryStatus>>>,
+    next_frame_id: Mutex<u8>,
+}
+
+impl Default for Transmitter {
+    fn default() -> Self {
+        return Transmitter::new();
+    }
+}
+
+impl Transmitter {
+    pub fn new() -> Transmitter {
+        return Transmitter {
+            queue: Mutex::new(VecDeque::new()),
+            pending: Mutex::new(HashMap::new()),
+            next_frame_id: Mutex::new(FIRST_FRAME_ID),
+        };
+    }
+
+    /// Queues a TX request carrying the data to the node and waits up to the timeout for its TX Status.
+    ///
+    /// The reader writes queued requests between frames, so a request waits at most one read timeout.
+    pub async fn send(&self, address: &str, data: &[u8], timeout: Duration) -> Result<Delivery, Error> {
+        let destination = parse_address(address)?;
+        let (sender, receiver) = oneshot::channel();
+        let (frame_id, tx_request) = {
+            let mut pending = self.pending.lock().unwrap();
+            let frame_id = self.frame_id(&pending)?;
+            let tx_request = tx_request(frame_id, destination, data)?;
+            pending.insert(frame_id, sender);
+            (frame_id, tx_request)
+        };
+        let data = HexString::from_bytes(&data.to_vec()).as_string();
+        self.queue.lock().unwrap().push_back((frame_id, tx_request.as_slice().to_vec()));
+        info!("command queued; address={} frame_id={} data={}", address, frame_id, data);
+        let status = match tokio::time::timeout(timeout, receiver).await {
+            Ok(Ok(status)) => status,
+            _ => {
+                // The caller is told the command failed, so it must not be written late and sent twice on retry.
+                self.queue.lock().unwrap().retain(|(queued, _)| *queued != frame_id);
+                self.pending.lock().unwrap().remove(&frame_id);
+                COMMANDS_COUNTER.with_label_values(&["timeout"]).inc();
+                warn!("command timed out; address={} frame_id={}", address, frame_id);
+                return Err(Error::Timeout);
+            }
+        };
+        let status = delivery_status_label(status);
+        COMMANDS_COUNTER.with_label_values(&[status]).inc();
+        info!("command delivered; address={} frame_id={} status={}", address, frame_id, status);
+        return Ok(Delivery { address: address.to_lowercase(), frame_id, data, status });
+    }
+
+    /// Returns the next frame id not waiting for a TX Status.
+    fn frame_id(&self, pending: &HashMap<u8, oneshot::Sender<DeliveryStatus>>) -> Result<u8, Error> {
+        let mut next_frame_id = self.next_frame_id.lock().unwrap();
+        for _ in FIRST_FRAME_ID..=u8::MAX {
+            let frame_id = *next_frame_id;
+            *next_frame_id = if frame_id == u8::MAX { FIRST_FRAME_ID } else { frame_id + 1 };
+            if !pending.contains_key(&frame_id) {
+                return Ok(frame_id);
+            }
+        }
+        return Err(Error::Busy);
+    }
+
+    /// Takes the TX request frames queued since the last call.
+    pub fn take_queued(&self) -> Vec<Vec<u8>> {
+        return self.queue.lock().unwrap().drain(..).map(|(_, frame)| frame).collect();
+    }
+
+    /// Hands the TX Status to the command waiting for it, returns `false` if none is.
+    pub fn status_received(&self, status: &TxStatus) -> bool {
+        return match self.pending.lock().unwrap().remove(&status.frame_id) {
+            Some(sender) => sender.send(status.status).is_ok(),
+            None => false,
+        };
+    }
+}
+
+/// Posts the command to the commands endpoint of the server running with the configuration, returning whether
+/// it was delivered and the response body.
+pub async fn post(config: &Config, address: &str, command: &Command) -> Result<(bool, String), String> {
+    if config.http.tls_cert.is_some() {
+        return Err("the server serves https, which --server does not support".to_string());
+    }
+    let authorization = auth::admin_authorization(config)
+        .ok_or_else(|| "commands.token or an admin auth credential is required".to_string())?;
+    let mut bind = config.bind_address().map_err(|e| e.to_string())?;
+    if bind.ip().is_unspecified() {
+        bind.set_ip(if bind.is_ipv4() { [127, 0, 0, 1].into() } else { std::net::Ipv6Addr::LOCALHOST.into() });
+    }
+    let body = serde_json::to_vec(command).expect("command serializable");
+    let request = Request::builder().method(Method::POST)
+        .uri(format!("http://{}/api/nodes/{}/commands", bind, address))
+        .header(CONTENT_TYPE, "application/json")
+        .header(AUTHORIZATION, authorization)
+        .body(Body::from(body))
+        .map_err(|e| e.to_string())?;
+    let timeout = Duration::from_secs(config.commands.timeout + 5);
+    let response = tokio::time::timeout(timeout, Client::new().request(request)).await
+        .map_err(|_| "server did not answer".to_string())?
+        .map_err(|e| e.to_string())?;
+    let delivered = response.status().is_success();
+    let body = hyper::body::to_bytes(response.into_body()).await.map_err(|e| e.to_string())?;
+    return Ok((delivered, String::from_utf8_lossy(&body).to_string()));
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+    use super::*;
+
+    #[test]
+    fn parse() {
+        assert_eq!("force_reading".parse(), Ok(Command::ForceReading));
+        assert_eq!("set_report_interval=300".parse(), Ok(Command::SetReportInterval { seconds: 300 }));
+        assert_eq!("set_deltas=0.1,0.5".parse(), Ok(Command::SetDeltas { temperature: 0.1, humidity: 0.5 }));
+        assert_eq!("raw=0102".parse(), Ok(Command::Raw { data: "0102".to_string() }));
+        assert!(matches!("set_report_interval".parse::<Command>(), Err(Error::InvalidCommand(_))));
+        assert!(matches!("reboot".parse::<Command>(), Err(Error::InvalidCommand(_))));
+    }
+
+    #[test]
+    fn encode() {
+        assert_eq!(Command::ForceReading.encode(), Ok(vec![0x01]));
+        assert_eq!(Command::SetReportInterval { seconds: 300 }.encode(), Ok(vec![0x02, 0x01, 0x2c]));
+        assert_eq!(Command::SetDeltas { temperature: 0.1, humidity: 0.5 }.encode(), Ok(vec![0x03, 0x00, 0x0a, 0x00, 0x32]));
+        assert_eq!(Command::Raw { data: "0A0b".to_string() }.encode(), Ok(vec![0x0a, 0x0b]));
+        assert_eq!(Command::Raw { data: "0g".to_string() }.encode(), Err(Error::InvalidData("0g".to_string())));
+        assert_eq!(Command::SetDeltas { temperature: -1.0, humidity: 0.5 }.encode(), Err(Error::InvalidDelta(-1.0)));
+    }
+
+    #[test]
+    fn address() {
+        assert_eq!(parse_address("5678"), Ok(Address::Short([0x56, 0x78])));
+        assert_eq!(parse_address("0013A20040640375"),
+                   Ok(Address::Long([0x00, 0x13, 0xa2, 0x00, 0x40, 0x64, 0x03, 0x75])));
+        assert!(parse_address("567").is_err());
+    }
+
+    #[tokio::test]
+    async fn send_too_long() {
+        let transmitter = Transmitter::new();
+        let actual = transmitter.send("5678", &[0x41; xbee::MAX_TX_DATA + 1], Duration::from_secs(5)).await;
+        assert_eq!(actual.unwrap_err(), Error::DataTooLong(101));
+        assert!(transmitter.take_queued().is_empty());
+    }
+
+    #[tokio::test]
+    async fn send() {
+        let transmitter = Arc::new(Transmitter::new());
+        let sending = transmitter.clone();
+        let delivery = tokio::spawn(async move {
+            return sending.send("5678", &[0x01], Duration::from_secs(5)).await;
+        });
+        let queued = loop {
+            let queued = transmitter.take_queued();
+            if !queued.is_empty() {
+                break queued;
+            }
+            tokio::task::yield_now().await;
+        };
+        assert_eq!(queued, [vec![0x7e, 0x00, 0x06, 0x01, 0x02, 0x56, 0x78, 0x00, 0x01, 0x2d]]);
+        assert!(transmitter.status_received(&TxStatus { frame_id: 0x02, status: DeliveryStatus::NoAck }));
+        let delivery = delivery.await.unwrap().unwrap();
+        assert_eq!(delivery, Delivery { address: "5678".to_string(), frame_id: 0x02, data: "01".to_string(), status: "no_ack" });
+        assert!(!transmitter.status_received(&TxStatus { frame_id: 0x02, status: DeliveryStatus::Success }));
+    }
+
+    #[tokio::test]
+    async fn send_timeout() {
+        let transmitter = Transmitter::new();
+        let actual = transmitter.send("5678", &[0x01], Duration::from_millis(10)).await;
+        assert_eq!(actual, Err(Error::Timeout));
+        assert!(transmitter.pending.lock().unwrap().is_empty());
+        assert!(transmitter.take_queued().is_empty());
+    }
+}
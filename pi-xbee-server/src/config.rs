@@ -0,0 +1,1180 @@
+use std::collections::BTreeMap;
+use std::net::SocketAddr;
+use std::path::{Path, PathBuf};
+use std::{fmt, fs, io};
+use clap::{Parser, Subcommand};
+use log::LevelFilter;
+use serde::{Deserialize, Serialize};
+
+const BAUD_RATES: [u32; 8] = [1_200, 2_400, 4_800, 9_600, 19_200, 38_400, 57_600, 115_200];
+
+#[derive(Parser, Debug, Default)]
+#[command(version, about = "Exports XBee sensor readings received on a serial port.")]
+pub struct Args {
+    /// Path of the TOML configuration file
+    #[arg(short, long, env = "PI_XBEE_CONFIG", global = true)]
+    pub config: Option<PathBuf>,
+    /// Transport to the XBee module (rppal, tty, tcp, loopback)
+    #[arg(long, env = "PI_XBEE_TRANSPORT", global = true, value_parser = parse_transport)]
+    pub transport: Option<TransportKind>,
+    /// Serial device path
+    #[arg(long, env = "PI_XBEE_DEVICE", global = true)]
+    pub device: Option<String>,
+    /// `host:port` of the tcp transport
+    #[arg(long, env = "PI_XBEE_ADDRESS", global = true)]
+    pub address: Option<String>,
+    /// Serial baud rate
+    #[arg(long, env = "PI_XBEE_BAUD", global = true)]
+    pub baud: Option<u32>,
+    /// HTTP bind address
+    #[arg(long, env = "PI_XBEE_BIND", global = true)]
+    pub bind: Option<String>,
+    /// Log level (error, warn, info, debug, trace)
+    #[arg(long, env = "PI_XBEE_LOG_LEVEL", global = true)]
+    pub log_level: Option<String>,
+    /// Node name mapping as `address=name`, may be repeated
+    #[arg(long = "node", env = "PI_XBEE_NODES", value_delimiter = ',', global = true)]
+    pub nodes: Vec<String>,
+    #[command(subcommand)]
+    pub mode: Option<Mode>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum Mode {
+    /// Export the readings over HTTP and to the sinks (the default)
+    Serve,
+    /// Print the fields of a hex API frame, e.g. `7e 00 04 08 01 44 42 70`
+    Decode {
+        #[arg(num_args = 1.., required = true)]
+        frame: Vec<String>,
+    },
+    /// Print every frame received on the transport
+    Listen,
+    /// Transmit a command to a node or an AT command to the local module and print the response
+    Send(SendArgs),
+    /// Print the frames of a capture, hex frames one per line or raw bytes as read from the serial port
+    Replay {
+        capture: PathBuf,
+    },
+}
+
+#[derive(clap::Args, Debug)]
+pub struct SendArgs {
+    /// Hex address of the node
+    #[arg(required_unless_present = "at")]
+    pub node: Option<String>,
+    /// force_reading, set_report_interval=<seconds>, set_deltas=<temperature>,<humidity> or raw=<hex>
+    #[arg(required_unless_present = "at")]
+    pub command: Option<String>,
+    /// AT command to the local module instead, e.g. `DB` or `NJ=ff`
+    #[arg(long, conflicts_with_all = ["node", "command", "server"])]
+    pub at: Option<String>,
+    /// Send through the commands endpoint of the running server, which holds the transport
+    #[arg(long)]
+    pub server: bool,
+}
+
+/// Whether the URL can be requested by the [`crate::https`] client.
+fn http_url(url: &str) -> bool {
+    return url.starts_with("http://") || url.starts_with("https://");
+}
+
+fn parse_transport(value: &str) -> Result<TransportKind, String> {
+    return TransportKind::deserialize(toml::Value::String(value.to_string()))
+        .map_err(|e| e.to_string());
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub serial: SerialConfig,
+    pub http: HttpConfig,
+    pub auth: AuthConfig,
+    pub log: LogConfig,
+    pub payload: PayloadConfig,
+    pub sinks: SinksConfig,
+    pub health: HealthConfig,
+    pub coordinator: CoordinatorConfig,
+    pub api: ApiConfig,
+    pub sqlite: SqliteConfig,
+    pub history: HistoryConfig,
+    pub mqtt: MqttConfig,
+    pub influx: InfluxConfig,
+    pub push: PushConfig,
+    pub commands: CommandsConfig,
+    pub alerts: Vec<AlertRule>,
+    pub notifiers: Vec<NotifierConfig>,
+    /// Node names by lower case hex source address.
+    pub nodes: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SerialConfig {
+    pub transport: TransportKind,
+    /// Device path of the `rppal` and `tty` transports.
+    pub device: String,
+    /// `host:port` of the `tcp` transport, e.g. a ser2net raw port.
+    pub address: String,
+    pub baud: u32,
+    pub parity: Parity,
+    pub data_bits: u8,
+    pub stop_bits: u8,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum TransportKind {
+    /// Raspberry Pi UART through `rppal`.
+    Rppal,
+    /// Any serial tty, e.g. a USB XBee explorer.
+    Tty,
+    /// Raw TCP socket, e.g. ser2net.
+    Tcp,
+    /// In-memory echo, reads back what was written.
+    Loopback,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Parity {
+    None,
+    Even,
+    Odd,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HttpConfig {
+    pub bind: String,
+    /// PEM certificate chain; HTTPS is served instead of HTTP if given together with `tls_key`.
+    pub tls_cert: Option<PathBuf>,
+    /// PEM private key of the certificate, PKCS#8, PKCS#1 or SEC1.
+    pub tls_key: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct AuthConfig {
+    /// Require a read credential for the dashboard, the JSON API and the stream.
+    pub enabled: bool,
+    /// Require a read credential for `/metrics` too, which stays open by default.
+    pub protect_metrics: bool,
+    pub credentials: Vec<Credential>,
+}
+
+/** Token or username and password granting a scope; the health probes never require one. */
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct Credential {
+    pub scope: Scope,
+    /// Accepted as `Authorization: Bearer <token>`.
+    pub token: Option<String>,
+    /// Accepted with `password` as `Authorization: Basic`, which browsers prompt for.
+    pub username: Option<String>,
+    pub password: Option<String>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Eq, PartialOrd, Ord, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Scope {
+    /// The dashboard, the JSON API, the stream and `/metrics`.
+    Read,
+    /// Everything, including the commands endpoint.
+    Admin,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct LogConfig {
+    pub level: String,
+}
+
+/** Format of the received payload and, for raw payloads, the offsets of the HTU21 raw values within it. */
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PayloadConfig {
+    pub format: PayloadFormat,
+    pub temperature_offset: usize,
+    pub humidity_offset: usize,
+    /// Each raw value is followed by its HTU21 CRC byte.
+    pub crc: bool,
+    /// Offset of a byte holding the battery level in percent, if the nodes send one.
+    pub battery_offset: Option<usize>,
+    /// Offset of a big endian `u16` packet counter, if the nodes send one, to detect lost and duplicate packets.
+    pub sequence_offset: Option<usize>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum PayloadFormat {
+    /// HTU21 raw values, battery level and sequence number at the configured offsets.
+    Raw,
+    /// Versioned payload of the `payload` crate, which ignores the offsets.
+    Tlv,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SinksConfig {
+    /// Serve gauges on `/metrics`.
+    pub prometheus: bool,
+    /// Log every reading at info level.
+    pub log: bool,
+    /// Store every reading in the SQLite database.
+    pub sqlite: bool,
+    /// Publish every reading to the MQTT broker.
+    pub mqtt: bool,
+    /// Write every reading as InfluxDB line protocol.
+    pub influx: bool,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HealthConfig {
+    /// Seconds without any valid frame after which the pipeline counts as broken.
+    pub frame_timeout: u64,
+    /// Seconds without a reading after which a node counts as stale.
+    pub node_timeout: u64,
+    /// What happens to the gauges of a stale node.
+    pub stale: StalePolicy,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum StalePolicy {
+    /// Keep exporting the last values.
+    Keep,
+    /// Set `pi_xbee_node_stale` to 1 while the node is stale.
+    Mark,
+    /// Remove the node's sensor gauges until its next reading.
+    Drop,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+#[serde(default, deny_unknown_fields)]
+pub struct CoordinatorConfig {
+    /// Seconds between `DB` (RSSI of the last packet) queries of the coordinator, 0 disables them.
+    pub rssi_interval: u64,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct ApiConfig {
+    /// Serve the JSON API under `/api`.
+    pub enabled: bool,
+    /// Number of readings per node kept for `/api/nodes/{address}/readings`.
+    pub recent_readings: usize,
+    /// Serve the dashboard on `/`.
+    pub dashboard: bool,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct SqliteConfig {
+    /// Path of the database file, created if missing.
+    pub path: PathBuf,
+    /// Days readings are kept, 0 keeps them forever.
+    pub retention_days: u64,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct HistoryConfig {
+    /// Serve `/api/nodes/{address}/history`.
+    pub enabled: bool,
+    /// Seconds per stored bucket, the smallest step of a query.
+    pub resolution: u64,
+    /// Days buckets are kept.
+    pub retention_days: u64,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct MqttConfig {
+    pub host: String,
+    pub port: u16,
+    pub client_id: String,
+    pub username: Option<String>,
+    pub password: Option<String>,
+    /// Topic of each value, `{node}`, `{address}` and `{value}` are replaced.
+    pub topic: String,
+    /// 0 (at most once), 1 (at least once) or 2 (exactly once).
+    pub qos: u8,
+    pub retain: bool,
+    /// Seconds between keep alive pings.
+    pub keep_alive: u64,
+    /// Publish Home Assistant MQTT discovery config when a node is first seen.
+    pub discovery: bool,
+    pub discovery_prefix: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct InfluxConfig {
+    pub output: InfluxOutput,
+    /// Write endpoint of the `http` output, e.g. `/api/v2/write?org=home&bucket=xbee` or VictoriaMetrics' `/write`.
+    pub url: String,
+    /// Sent as `Authorization: Token <token>` by the `http` output.
+    pub token: Option<String>,
+    /// File the `file` output appends to.
+    pub path: PathBuf,
+    /// `host:port` the `udp` output sends to.
+    pub address: String,
+    pub measurement: String,
+    /// Lines written at most per request.
+    pub batch_size: usize,
+    /// Seconds between writes of a partial batch.
+    pub flush_interval: u64,
+    /// Lines kept while the output fails, the oldest are dropped beyond.
+    pub buffer_size: usize,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum InfluxOutput {
+    Http,
+    File,
+    Udp,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct PushConfig {
+    /// Push the metrics periodically, for servers behind NAT that Prometheus cannot scrape.
+    pub enabled: bool,
+    pub output: PushOutput,
+    /// Pushgateway base URL, e.g. `http://pushgateway:9091`, or remote write endpoint, e.g.
+    /// `http://prometheus:9090/api/v1/write`.
+    pub url: String,
+    /// Sent as `Authorization: Bearer <token>`.
+    pub token: Option<String>,
+    /// `job` label of the pushed series, the Pushgateway's grouping key.
+    pub job: String,
+    /// `instance` label of the pushed series, part of the Pushgateway's grouping key if set.
+    pub instance: Option<String>,
+    /// Seconds between pushes.
+    pub interval: u64,
+    /// Remote write snapshots kept while the endpoint is unreachable, the oldest are dropped beyond.
+    pub buffer_size: usize,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum PushOutput {
+    /// Text format, replacing the previous push of the grouping key; only the latest snapshot is kept.
+    Pushgateway,
+    /// Snappy compressed protobuf with timestamps; snapshots are buffered while the endpoint fails.
+    RemoteWrite,
+}
+
+#[derive(Deserialize, Debug, PartialEq)]
+#[serde(default, deny_unknown_fields)]
+pub struct CommandsConfig {
+    /// Serve `POST /api/nodes/{address}/commands`, which transmits to the nodes.
+    pub enabled: bool,
+    /// Required as `Authorization: Bearer <token>` by the endpoint and sent by `send --server`.
+    pub token: Option<String>,
+    /// Seconds to wait for the coordinator's TX Status.
+    pub timeout: u64,
+}
+
+/** Condition on a value of a node's readings that fires notifications. */
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct AlertRule {
+    pub name: String,
+    pub metric: Metric,
+    /// Name or address of the node, all nodes if omitted.
+    pub node: Option<String>,
+    pub comparison: Comparison,
+    pub threshold: f64,
+    /// How far the value must move back past the threshold before the alert resolves.
+    #[serde(default)]
+    pub hysteresis: f64,
+    /// Seconds the condition must hold before the alert fires.
+    #[serde(default)]
+    pub hold: u64,
+    /// Names of the notifiers to send to, all notifiers if omitted.
+    pub notify: Option<Vec<String>>,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "snake_case")]
+pub enum Metric {
+    Temperature,
+    Humidity,
+    Rssi,
+    Battery,
+}
+
+#[derive(Deserialize, Serialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum Comparison {
+    Above,
+    Below,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone)]
+#[serde(deny_unknown_fields)]
+pub struct NotifierConfig {
+    pub name: String,
+    pub kind: NotifierKind,
+    /// `webhook`: URL the notification is POSTed to as JSON.
+    #[serde(default)]
+    pub url: String,
+    /// `command`: program and arguments run with the notification as JSON on stdin.
+    #[serde(default)]
+    pub command: Vec<String>,
+    /// `mqtt`: topic the notification is published to as JSON, through the [mqtt] broker.
+    #[serde(default)]
+    pub topic: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Clone, Copy)]
+#[serde(rename_all = "lowercase")]
+pub enum NotifierKind {
+    Webhook,
+    Command,
+    Mqtt,
+}
+
+impl Default for Config {
+    fn default() -> Self {
+        return Config {
+            serial: SerialConfig::default(),
+            http: HttpConfig::default(),
+            auth: AuthConfig::default(),
+            log: LogConfig::default(),
+            payload: PayloadConfig::default(),
+            sinks: SinksConfig::default(),
+            health: HealthConfig::default(),
+            coordinator: CoordinatorConfig::default(),
+            api: ApiConfig::default(),
+            sqlite: SqliteConfig::default(),
+            history: HistoryConfig::default(),
+            mqtt: MqttConfig::default(),
+            influx: InfluxConfig::default(),
+            push: PushConfig::default(),
+            commands: CommandsConfig::default(),
+            alerts: Vec::new(),
+            notifiers: Vec::new(),
+            nodes: BTreeMap::new(),
+        };
+    }
+}
+
+impl Default for SerialConfig {
+    fn default() -> Self {
+        return SerialConfig {
+            transport: if cfg!(feature = "rppal") { TransportKind::Rppal } else { TransportKind::Tty },
+            device: "/dev/serial0".to_string(),
+            address: String::new(),
+            baud: 57_600,
+            parity: Parity::None,
+            data_bits: 8,
+            stop_bits: 1,
+        };
+    }
+}
+
+impl Default for HttpConfig {
+    fn default() -> Self {
+        return HttpConfig { bind: "0.0.0.0:8080".to_string(), tls_cert: None, tls_key: None };
+    }
+}
+
+impl Default for LogConfig {
+    fn default() -> Self {
+        return LogConfig { level: "info".to_string() };
+    }
+}
+
+impl Default for PayloadConfig {
+    fn default() -> Self {
+        return PayloadConfig {
+            format: PayloadFormat::Tlv,
+            temperature_offset: 0,
+            humidity_offset: 2,
+            crc: false,
+            battery_offset: None,
+            sequence_offset: None,
+        };
+    }
+}
+
+impl Default for HealthConfig {
+    fn default() -> Self {
+        return HealthConfig { frame_timeout: 900, node_timeout: 1_800, stale: StalePolicy::Keep };
+    }
+}
+
+impl Default for SinksConfig {
+    fn default() -> Self {
+        return SinksConfig { prometheus: true, log: true, sqlite: false, mqtt: false, influx: false };
+    }
+}
+
+impl Default for SqliteConfig {
+    fn default() -> Self {
+        return SqliteConfig { path: PathBuf::from("/var/lib/pi-xbee-server/readings.db"), retention_days: 30 };
+    }
+}
+
+impl Default for ApiConfig {
+    fn default() -> Self {
+        return ApiConfig { enabled: true, recent_readings: 100, dashboard: true };
+    }
+}
+
+impl Default for HistoryConfig {
+    fn default() -> Self {
+        return HistoryConfig { enabled: true, resolution: 60, retention_days: 7 };
+    }
+}
+
+impl Default for MqttConfig {
+    fn default() -> Self {
+        return MqttConfig {
+            host: "localhost".to_string(),
+            port: 1_883,
+            client_id: "pi-xbee-server".to_string(),
+            username: None,
+            password: None,
+            topic: "xbee/{node}/{value}".to_string(),
+            qos: 0,
+            retain: false,
+            keep_alive: 30,
+            discovery: false,
+            discovery_prefix: "homeassistant".to_string(),
+        };
+    }
+}
+
+impl Default for InfluxConfig {
+    fn default() -> Self {
+        return InfluxConfig {
+            output: InfluxOutput::Http,
+            url: "http://localhost:8086/api/v2/write?org=home&bucket=xbee&precision=ns".to_string(),
+            token: None,
+            path: PathBuf::from("/var/lib/pi-xbee-server/readings.lp"),
+            address: "localhost:8089".to_string(),
+            measurement: "xbee".to_string(),
+            batch_size: 100,
+            flush_interval: 10,
+            buffer_size: 10_000,
+        };
+    }
+}
+
+impl Default for PushConfig {
+    fn default() -> Self {
+        return PushConfig {
+            enabled: false,
+            output: PushOutput::Pushgateway,
+            url: "http://localhost:9091".to_string(),
+            token: None,
+            job: "pi-xbee-server".to_string(),
+            instance: None,
+            interval: 15,
+            buffer_size: 240,
+        };
+    }
+}
+
+impl Default for CommandsConfig {
+    fn default() -> Self {
+        return CommandsConfig { enabled: false, token: None, timeout: 10 };
+    }
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Read(PathBuf, io::Error),
+    Parse(PathBuf, toml::de::Error),
+    UnsupportedTransport(TransportKind),
+    MissingAddress,
+    InvalidBaud(u32),
+    InvalidDataBits(u8),
+    InvalidStopBits(u8),
+    InvalidBind(String),
+    IncompleteTls,
+    InvalidAuth(&'static str),
+    InvalidLogLevel(String),
+    InvalidNodeMapping(String),
+    InvalidNodeAddress(String),
+    OverlappingPayloadOffsets(usize, usize),
+    OverlappingBatteryOffset(usize),
+    OverlappingSequenceOffset(usize),
+    InvalidTimeout(&'static str),
+    InvalidRecentReadings,
+    MissingSqlitePath,
+    InvalidHistory(&'static str),
+    MissingMqttHost,
+    InvalidMqttTopic(String),
+    InvalidMqttQos(u8),
+    InvalidInflux(&'static str),
+    InvalidPush(&'static str),
+    MissingCommandsToken,
+    InvalidAlert(String, &'static str),
+    InvalidNotifier(String, &'static str),
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Read(path, e) => write!(f, "unable to read {}: {}", path.display(), e),
+            Error::Parse(path, e) => write!(f, "unable to parse {}: {}", path.display(), e),
+            Error::UnsupportedTransport(transport) =>
+                write!(f, "serial.transport {:?} is not supported by this build", transport),
+            Error::MissingAddress => write!(f, "serial.address is required by the tcp transport"),
+            Error::InvalidBaud(baud) =>
+                write!(f, "serial.baud {} is not one of {:?}", baud, BAUD_RATES),
+            Error::InvalidDataBits(bits) => write!(f, "serial.data_bits {} is not within 5..=8", bits),
+            Error::InvalidStopBits(bits) => write!(f, "serial.stop_bits {} is not 1 or 2", bits),
+            Error::InvalidBind(bind) =>
+                write!(f, "http.bind {:?} is not a socket address like 0.0.0.0:8080", bind),
+            Error::IncompleteTls => write!(f, "http.tls_cert and http.tls_key must be given together"),
+            Error::InvalidAuth(reason) => write!(f, "auth {}", reason),
+            Error::InvalidLogLevel(level) =>
+                write!(f, "log.level {:?} is not one of off, error, warn, info, debug, trace", level),
+            Error::InvalidNodeMapping(entry) =>
+                write!(f, "node mapping {:?} is not of the form address=name", entry),
+            Error::InvalidNodeAddress(address) =>
+                write!(f, "node address {:?} is not a 4 or 16 digit hex address", address),
+            Error::OverlappingPayloadOffsets(temperature, humidity) =>
+                write!(f, "payload offsets overlap; temperature_offset={} humidity_offset={}", temperature, humidity),
+            Error::OverlappingBatteryOffset(battery) =>
+                write!(f, "payload battery_offset {} overlaps a raw value", battery),
+            Error::OverlappingSequenceOffset(sequence) =>
+                write!(f, "payload sequence_offset {} overlaps a raw value or the battery level", sequence),
+            Error::InvalidTimeout(name) => write!(f, "{} must be greater than 0", name),
+            Error::InvalidRecentReadings => write!(f, "api.recent_readings must be greater than 0"),
+            Error::MissingSqlitePath => write!(f, "sqlite.path is required by the sqlite sink"),
+            Error::InvalidHistory(name) => write!(f, "{} must be greater than 0", name),
+            Error::MissingMqttHost => write!(f, "mqtt.host is required by the mqtt sink"),
+            Error::InvalidMqttTopic(topic) => write!(f, "mqtt.topic {:?} does not contain {{value}}", topic),
+            Error::InvalidMqttQos(qos) => write!(f, "mqtt.qos {} is not 0, 1 or 2", qos),
+            Error::InvalidInflux(reason) => write!(f, "influx {}", reason),
+            Error::InvalidPush(reason) => write!(f, "push {}", reason),
+            Error::MissingCommandsToken =>
+                write!(f, "commands.token or an admin auth credential is required by the commands endpoint"),
+            Error::InvalidAlert(name, reason) => write!(f, "alert {:?} {}", name, reason),
+            Error::InvalidNotifier(name, reason) => write!(f, "notifier {:?} {}", name, reason),
+        }
+    }
+}
+
+impl Config {
+    /// Loads the configuration file named by the arguments (if any) and applies the overrides.
+    pub fn load(args: &Args) -> Result<Config, Error> {
+        let mut config = match &args.config {
+            Some(path) => Config::read(path)?,
+            None => Config::default(),
+        };
+        config.apply(args)?;
+        config.validate()?;
+        return Ok(config);
+    }
+
+    fn read(path: &Path) -> Result<Config, Error> {
+        let content = fs::read_to_string(path)
+            .map_err(|e| Error::Read(path.to_path_buf(), e))?;
+        return toml::from_str(&content)
+            .map_err(|e| Error::Parse(path.to_path_buf(), e));
+    }
+
+    fn apply(&mut self, args: &Args) -> Result<(), Error> {
+        if let Some(transport) = args.transport {
+            self.serial.transport = transport;
+        }
+        if let Some(device) = &args.device {
+            self.serial.device = device.clone();
+        }
+        if let Some(address) = &args.address {
+            self.serial.address = address.clone();
+        }
+        if let Some(baud) = args.baud {
+            self.serial.baud = baud;
+        }
+        if let Some(bind) = &args.bind {
+            self.http.bind = bind.clone();
+        }
+        if let Some(level) = &args.log_level {
+            self.log.level = level.clone();
+        }
+        for entry in args.nodes.iter().filter(|entry| !entry.is_empty()) {
+            match entry.split_once('=') {
+                Some((address, name)) => {
+                    self.nodes.insert(address.trim().to_string(), name.trim().to_string());
+                }
+                None => return Err(Error::InvalidNodeMapping(entry.clone())),
+            }
+        }
+        return Ok(());
+    }
+
+    fn validate(&mut self) -> Result<(), Error> {
+        if self.serial.transport == TransportKind::Rppal && !cfg!(feature = "rppal") {
+            return Err(Error::UnsupportedTransport(self.serial.transport));
+        }
+        if self.serial.transport == TransportKind::Tcp && self.serial.address.is_empty() {
+            return Err(Error::MissingAddress);
+        }
+        if !BAUD_RATES.contains(&self.serial.baud) {
+            return Err(Error::InvalidBaud(self.serial.baud));
+        }
+        if !(5..=8).contains(&self.serial.data_bits) {
+            return Err(Error::InvalidDataBits(self.serial.data_bits));
+        }
+        if !(1..=2).contains(&self.serial.stop_bits) {
+            return Err(Error::InvalidStopBits(self.serial.stop_bits));
+        }
+        self.bind_address()?;
+        if self.http.tls_cert.is_some() != self.http.tls_key.is_some() {
+            return Err(Error::IncompleteTls);
+        }
+        self.log_level()?;
+        let mut nodes = BTreeMap::new();
+        for (address, name) in &self.nodes {
+            let valid = (address.len() == 4 || address.len() == 16)
+                && address.chars().all(|c| c.is_ascii_hexdigit());
+            if !valid {
+                return Err(Error::InvalidNodeAddress(address.clone()));
+            }
+            nodes.insert(address.to_lowercase(), name.clone());
+        }
+        self.nodes = nodes;
+        if self.payload.format == PayloadFormat::Raw {
+            let temperature = self.payload.temperature_offset;
+            let humidity = self.payload.humidity_offset;
+            if temperature.abs_diff(humidity) < self.payload.value_length() {
+                return Err(Error::OverlappingPayloadOffsets(temperature, humidity));
+            }
+            if let Some(battery) = self.payload.battery_offset {
+                let value_length = self.payload.value_length();
+                if [temperature, humidity].iter().any(|offset| (*offset..offset + value_length).contains(&battery)) {
+                    return Err(Error::OverlappingBatteryOffset(battery));
+                }
+            }
+            if let Some(sequence) = self.payload.sequence_offset {
+                let value_length = self.payload.value_length();
+                let mut used = [temperature, humidity].into_iter()
+                    .map(|offset| offset..offset + value_length)
+                    .chain(self.payload.battery_offset.map(|battery| battery..battery + 1));
+                if used.any(|range| range.start < sequence + 2 && sequence < range.end) {
+                    return Err(Error::OverlappingSequenceOffset(sequence));
+                }
+            }
+        }
+        if self.health.frame_timeout == 0 {
+            return Err(Error::InvalidTimeout("health.frame_timeout"));
+        }
+        if self.health.node_timeout == 0 {
+            return Err(Error::InvalidTimeout("health.node_timeout"));
+        }
+        if self.api.recent_readings == 0 {
+            return Err(Error::InvalidRecentReadings);
+        }
+        if self.sinks.sqlite && self.sqlite.path.as_os_str().is_empty() {
+            return Err(Error::MissingSqlitePath);
+        }
+        if self.history.resolution == 0 {
+            return Err(Error::InvalidHistory("history.resolution"));
+        }
+        if self.history.retention_days == 0 {
+            return Err(Error::InvalidHistory("history.retention_days"));
+        }
+        if self.sinks.mqtt && self.mqtt.host.is_empty() {
+            return Err(Error::MissingMqttHost);
+        }
+        if !self.mqtt.topic.contains("{value}") {
+            return Err(Error::InvalidMqttTopic(self.mqtt.topic.clone()));
+        }
+        if self.mqtt.qos > 2 {
+            return Err(Error::InvalidMqttQos(self.mqtt.qos));
+        }
+        if self.sinks.influx {
+            self.validate_influx()?;
+        }
+        if self.push.enabled {
+            self.validate_push()?;
+        }
+        self.validate_auth()?;
+        let admin = self.auth.credentials.iter().any(|credential| credential.scope == Scope::Admin);
+        if self.commands.enabled && !admin && self.commands.token.as_ref().is_none_or(|token| token.is_empty()) {
+            return Err(Error::MissingCommandsToken);
+        }
+        if self.commands.timeout == 0 {
+            return Err(Error::InvalidTimeout("commands.timeout"));
+        }
+        self.validate_alerts()?;
+        return Ok(());
+    }
+
+    fn validate_influx(&self) -> Result<(), Error> {
+        let influx = &self.influx;
+        if influx.output == InfluxOutput::Http && !http_url(&influx.url) {
+            return Err(Error::InvalidInflux("url must start with http:// or https://"));
+        }
+        if influx.output == InfluxOutput::Udp && influx.address.is_empty() {
+            return Err(Error::InvalidInflux("address is required by the udp output"));
+        }
+        if influx.measurement.is_empty() {
+            return Err(Error::InvalidInflux("measurement must not be empty"));
+        }
+        if influx.batch_size == 0 || influx.flush_interval == 0 {
+            return Err(Error::InvalidInflux("batch_size and flush_interval must be greater than 0"));
+        }
+        if influx.buffer_size < influx.batch_size {
+            return Err(Error::InvalidInflux("buffer_size must be at least batch_size"));
+        }
+        return Ok(());
+    }
+
+    fn validate_push(&self) -> Result<(), Error> {
+        let push = &self.push;
+        if !http_url(&push.url) {
+            return Err(Error::InvalidPush("url must start with http:// or https://"));
+        }
+        if push.job.is_empty() || push.job.contains('/') {
+            return Err(Error::InvalidPush("job must be a non-empty path segment"));
+        }
+        if push.instance.as_ref().is_some_and(|instance| instance.is_empty() || instance.contains('/')) {
+            return Err(Error::InvalidPush("instance must be a non-empty path segment"));
+        }
+        if push.interval == 0 || push.buffer_size == 0 {
+            return Err(Error::InvalidPush("interval and buffer_size must be greater than 0"));
+        }
+        return Ok(());
+    }
+
+    fn validate_auth(&self) -> Result<(), Error> {
+        let auth = &self.auth;
+        for credential in &auth.credentials {
+            let valid = match (&credential.token, &credential.username, &credential.password) {
+                (Some(token), None, None) => !token.is_empty(),
+                (None, Some(username), Some(password)) =>
+                    !username.is_empty() && !username.contains(':') && !password.is_empty(),
+                _ => false,
+            };
+            if !valid {
+                return Err(Error::InvalidAuth("credentials need a token, or a username without ':' and a password"));
+            }
+        }
+        let commands_token = self.commands.token.as_ref().is_some_and(|token| !token.is_empty());
+        if auth.enabled && auth.credentials.is_empty() && !commands_token {
+            return Err(Error::InvalidAuth("enabled requires at least one credential"));
+        }
+        if auth.protect_metrics && !auth.enabled {
+            return Err(Error::InvalidAuth("protect_metrics requires enabled"));
+        }
+        return Ok(());
+    }
+
+    fn validate_alerts(&self) -> Result<(), Error> {
+        for notifier in &self.notifiers {
+            let name = notifier.name.clone();
+            if self.notifiers.iter().filter(|other| other.name == notifier.name).count() > 1 {
+                return Err(Error::InvalidNotifier(name, "is defined more than once"));
+            }
+            match notifier.kind {
+                NotifierKind::Webhook if !http_url(&notifier.url) =>
+                    return Err(Error::InvalidNotifier(name, "url must start with http:// or https://")),
+                NotifierKind::Command if notifier.command.is_empty() =>
+                    return Err(Error::InvalidNotifier(name, "command must not be empty")),
+                NotifierKind::Mqtt if notifier.topic.is_empty() =>
+                    return Err(Error::InvalidNotifier(name, "topic must not be empty")),
+                _ => (),
+            }
+        }
+        for rule in &self.alerts {
+            if rule.hysteresis < 0.0 {
+                return Err(Error::InvalidAlert(rule.name.clone(), "hysteresis must not be negative"));
+            }
+            let unknown = rule.notify.iter().flatten()
+                .any(|name| !self.notifiers.iter().any(|notifier| &notifier.name == name));
+            if unknown {
+                return Err(Error::InvalidAlert(rule.name.clone(), "notifies an unknown notifier"));
+            }
+        }
+        return Ok(());
+    }
+
+    pub fn bind_address(&self) -> Result<SocketAddr, Error> {
+        return self.http.bind.parse()
+            .map_err(|_| Error::InvalidBind(self.http.bind.clone()));
+    }
+
+    pub fn log_level(&self) -> Result<LevelFilter, Error> {
+        return self.log.level.parse()
+            .map_err(|_| Error::InvalidLogLevel(self.log.level.clone()));
+    }
+
+    /// Returns the configured name of the node, or its address if it has none.
+    pub fn node_name<'a>(&'a self, address: &'a str) -> &'a str {
+        return self.nodes.get(address).map_or(address, |name| name.as_str());
+    }
+}
+
+impl PayloadConfig {
+    /// Length of a raw value, including its CRC byte if present.
+    pub fn value_length(&self) -> usize {
+        return if self.crc { 3 } else { 2 };
+    }
+
+    /// Length of the payload needed to hold both HTU21 raw values, the battery level and the sequence number.
+    pub fn length(&self) -> usize {
+        let values = self.temperature_offset.max(self.humidity_offset) + self.value_length();
+        let battery = self.battery_offset.map_or(values, |battery| values.max(battery + 1));
+        return self.sequence_offset.map_or(battery, |sequence| battery.max(sequence + 2));
+    }
+
+    /// Whether the payloads can carry a battery level.
+    pub fn carries_battery(&self) -> bool {
+        return self.format == PayloadFormat::Tlv || self.battery_offset.is_some();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(content: &str) -> Config {
+        let mut config: Config = toml::from_str(content).unwrap();
+        config.validate().unwrap();
+        return config;
+    }
+
+    #[test]
+    fn defaults() {
+        let config = parse("");
+        assert_eq!(config, Config::default());
+        assert_eq!(config.serial.baud, 57_600);
+        assert_eq!(config.bind_address().unwrap(), "0.0.0.0:8080".parse().unwrap());
+        assert_eq!(config.payload.format, PayloadFormat::Tlv);
+        assert_eq!(config.payload.length(), 4);
+    }
+
+    #[test]
+    fn full() {
+        let config = parse(r#"
+            [serial]
+            transport = "tty"
+            device = "/dev/ttyUSB0"
+            baud = 9600
+            parity = "even"
+
+            [http]
+            bind = "127.0.0.1:9100"
+            tls_cert = "/etc/pi-xbee-server/cert.pem"
+            tls_key = "/etc/pi-xbee-server/key.pem"
+
+            [auth]
+            enabled = true
+
+            [[auth.credentials]]
+            scope = "read"
+            token = "grafana"
+
+            [log]
+            level = "debug"
+
+            [sinks]
+            log = false
+
+            [nodes]
+            "0013A20040647346" = "cellar"
+        "#);
+        assert_eq!(config.serial.transport, TransportKind::Tty);
+        assert_eq!(config.serial.device, "/dev/ttyUSB0");
+        assert_eq!(config.serial.baud, 9600);
+        assert_eq!(config.serial.parity, Parity::Even);
+        assert_eq!(config.log_level().unwrap(), LevelFilter::Debug);
+        assert!(config.sinks.prometheus);
+        assert!(!config.sinks.log);
+        assert_eq!(config.auth.credentials[0].scope, Scope::Read);
+        assert_eq!(config.node_name("0013a20040647346"), "cellar");
+        assert_eq!(config.node_name("1234"), "1234");
+    }
+
+    #[test]
+    fn alerts() {
+        let config = parse(r#"
+            [[alerts]]
+            name = "cellar humid"
+            metric = "humidity"
+            node = "cellar"
+            comparison = "above"
+            threshold = 70.0
+            hysteresis = 2.0
+            hold = 300
+            notify = ["phone"]
+
+            [[notifiers]]
+            name = "phone"
+            kind = "webhook"
+            url = "https://ntfy.sh/xbee"
+        "#);
+        assert_eq!(config.alerts[0].metric, Metric::Humidity);
+        assert_eq!(config.alerts[0].comparison, Comparison::Above);
+        assert_eq!(config.notifiers[0].kind, NotifierKind::Webhook);
+
+        let mut config = config;
+        config.alerts[0].notify = Some(vec!["pager".to_string()]);
+        assert!(matches!(config.validate(), Err(Error::InvalidAlert(_, "notifies an unknown notifier"))));
+    }
+
+    #[test]
+    fn example() {
+        let config = parse(include_str!("../config.example.toml"));
+        assert_eq!(config, Config::default());
+    }
+
+    #[test]
+    fn unknown_field() {
+        let actual = toml::from_str::<Config>("[serial]\nbaudrate = 9600\n");
+        assert!(actual.is_err());
+    }
+
+    #[test]
+    fn overrides() {
+        let mut config = Config::default();
+        let args = Args {
+            transport: Some(parse_transport("loopback").unwrap()),
+            baud: Some(115_200),
+            bind: Some("[::]:8080".to_string()),
+            nodes: vec!["1234=attic".to_string()],
+            ..Args::default()
+        };
+        config.apply(&args).unwrap();
+        config.validate().unwrap();
+        assert_eq!(config.serial.transport, TransportKind::Loopback);
+        assert_eq!(config.serial.baud, 115_200);
+        assert_eq!(config.node_name("1234"), "attic");
+        assert!(parse_transport("uart").is_err());
+    }
+
+    #[test]
+    fn subcommands() {
+        use clap::CommandFactory;
+        Args::command().debug_assert();
+        let args = Args::parse_from(["pi-xbee-server", "send", "5678", "force_reading", "--transport", "loopback"]);
+        assert!(matches!(args.mode, Some(Mode::Send(SendArgs { node: Some(_), at: None, server: false, .. }))));
+        assert_eq!(args.transport, Some(TransportKind::Loopback));
+        assert!(Args::try_parse_from(["pi-xbee-server", "send", "--at", "DB", "5678"]).is_err());
+    }
+
+    #[test]
+    fn invalid() {
+        let mut config = Config::default();
+        config.serial.baud = 12_345;
+        assert!(matches!(config.validate(), Err(Error::InvalidBaud(12_345))));
+
+        let mut config = Config::default();
+        config.http.bind = "localhost".to_string();
+        assert!(matches!(config.validate(), Err(Error::InvalidBind(_))));
+
+        let mut config = Config::default();
+        config.log.level = "loud".to_string();
+        assert!(matches!(config.validate(), Err(Error::InvalidLogLevel(_))));
+
+        let mut config = Config::default();
+        config.nodes.insert("cellar".to_string(), "0013a20040647346".to_string());
+        assert!(matches!(config.validate(), Err(Error::InvalidNodeAddress(_))));
+
+        let mut config = Config::default();
+        let args = Args { nodes: vec!["1234".to_string()], ..Args::default() };
+        assert!(matches!(config.apply(&args), Err(Error::InvalidNodeMapping(_))));
+
+        let mut config = Config::default();
+        config.serial.transport = TransportKind::Tcp;
+        assert!(matches!(config.validate(), Err(Error::MissingAddress)));
+
+        let mut config = Config::default();
+        config.payload.format = PayloadFormat::Raw;
+        config.payload.humidity_offset = 1;
+        assert!(matches!(config.validate(), Err(Error::OverlappingPayloadOffsets(0, 1))));
+
+        let mut config = Config::default();
+        config.payload.format = PayloadFormat::Raw;
+        config.payload.crc = true;
+        assert!(matches!(config.validate(), Err(Error::OverlappingPayloadOffsets(0, 2))));
+
+        let mut config = Config::default();
+        config.payload.format = PayloadFormat::Raw;
+        config.payload.battery_offset = Some(3);
+        assert!(matches!(config.validate(), Err(Error::OverlappingBatteryOffset(3))));
+
+        let mut config = Config::default();
+        config.payload.format = PayloadFormat::Raw;
+        config.payload.battery_offset = Some(4);
+        config.payload.sequence_offset = Some(3);
+        assert!(matches!(config.validate(), Err(Error::OverlappingSequenceOffset(3))));
+        config.payload.sequence_offset = Some(5);
+        assert!(config.validate().is_ok());
+
+        let mut config = Config::default();
+        config.payload.format = PayloadFormat::Tlv;
+        config.payload.humidity_offset = 0;
+        assert!(config.validate().is_ok());
+
+        let mut config = Config::default();
+        config.health.node_timeout = 0;
+        assert!(matches!(config.validate(), Err(Error::InvalidTimeout("health.node_timeout"))));
+
+        let mut config = Config::default();
+        config.api.recent_readings = 0;
+        assert!(matches!(config.validate(), Err(Error::InvalidRecentReadings)));
+
+        let mut config = Config::default();
+        config.sinks.sqlite = true;
+        config.sqlite.path = PathBuf::new();
+        assert!(matches!(config.validate(), Err(Error::MissingSqlitePath)));
+
+        let mut config = Config::default();
+        config.history.resolution = 0;
+        assert!(matches!(config.validate(), Err(Error::InvalidHistory("history.resolution"))));
+
+        let mut config = Config::default();
+        config.mqtt.topic = "xbee/{node}".to_string();
+        assert!(matches!(config.validate(), Err(Error::InvalidMqttTopic(_))));
+
+        let mut config = Config::default();
+        config.sinks.influx = true;
+        config.influx.url = "https://influx.local/write".to_string();
+        assert!(config.validate().is_ok());
+        config.influx.url = "influx.local/write".to_string();
+        assert!(matches!(config.validate(), Err(Error::InvalidInflux(_))));
+
+        let mut config = Config::default();
+        config.push.enabled = true;
+        config.push.url = "https://prometheus.example.com/api/v1/write".to_string();
+        assert!(config.validate().is_ok());
+        config.push.instance = Some("garage/pi".to_string());
+        assert!(matches!(config.validate(), Err(Error::InvalidPush(_))));
+
+        let mut config = Config::default();
+        config.commands.enabled = true;
+        assert!(matches!(config.validate(), Err(Error::MissingCommandsToken)));
+        config.auth.credentials.push(Credential {
+            scope: Scope::Admin,
+            token: None,
+            username: Some("admin".to_string()),
+            password: Some("secret".to_string()),
+        });
+        assert!(config.validate().is_ok());
+        config.auth.credentials[0].username = Some("ad:min".to_string());
+        assert!(matches!(config.validate(), Err(Error::InvalidAuth(_))));
+
+        let mut config = Config::default();
+        config.auth.enabled = true;
+        assert!(matches!(config.validate(), Err(Error::InvalidAuth("enabled requires at least one credential"))));
+        config.commands.token = Some(String::new());
+        assert!(matches!(config.validate(), Err(Error::InvalidAuth("enabled requires at least one credential"))));
+
+        let mut config = Config::default();
+        config.http.tls_cert = Some(PathBuf::from("cert.pem"));
+        assert!(matches!(config.validate(), Err(Error::IncompleteTls)));
+    }
+}
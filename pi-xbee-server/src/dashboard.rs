@@ -0,0 +1,27 @@
+use std::sync::Arc;
+use warp::{Filter, Rejection, Reply};
+use crate::auth::{self, Auth};
+
+/// Single page dashboard backed by the JSON API, compiled into the binary.
+const INDEX_HTML: &str = include_str!("../assets/index.html");
+
+/// `GET /`: the dashboard.
+pub fn route(auth: Arc<Auth>) -> impl Filter<Extract=(impl Reply,), Error=Rejection> + Clone {
+    return warp::path::end()
+        .and(auth::read(auth))
+        .map(|| warp::reply::html(INDEX_HTML));
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn index() {
+        let auth = Arc::new(Auth::new(&crate::config::Config::default()));
+        let response = warp::test::request().path("/").reply(&route(auth)).await;
+        assert_eq!(response.status(), 200);
+        assert_eq!(response.headers()["content-type"], "text/html; charset=utf-8");
+        assert!(response.body().starts_with(b"<!DOCTYPE html>"));
+    }
+}
@@ -0,0 +1,194 @@
+use hex_string::HexString;
+use xbee::ApiIdentifier;
+use crate::command;
+use crate::config::{Config, PayloadFormat};
+use crate::reader::{self, SensorValues};
+
+fn hex(bytes: &[u8]) -> String {
+    return HexString::from_bytes(&bytes.to_vec()).as_string();
+}
+
+fn at_status_label(status: u8) -> &'static str {
+    return match status {
+        0 => "ok",
+        1 => "error",
+        2 => "invalid_command",
+        3 => "invalid_parameter",
+        _ => "other",
+    };
+}
+
+/// Decodes an API frame into named fields, using the same parsers and payload configuration as the server.
+pub fn fields(buffer: &[u8], config: &Config) -> Vec<(&'static str, String)> {
+    let mut fields = vec![("frame", hex(buffer))];
+    let api_identifier = match xbee::api_identifier(buffer) {
+        Ok(api_identifier) => api_identifier,
+        Err(e) => {
+            fields.push(("error", reader::xbee_error_label(&e).to_string()));
+            return fields;
+        }
+    };
+    fields.push(("api_identifier", reader::api_identifier_label(api_identifier).to_string()));
+    let parsed = match api_identifier {
+        ApiIdentifier::Rx64 | ApiIdentifier::Rx16 => xbee::parse_rx_packet(buffer).map(|packet| {
+            let address = hex(packet.source.as_slice());
+            fields.push(("node", config.node_name(&address).to_string()));
+            fields.insert(fields.len() - 1, ("address", address));
+            fields.push(("rssi", format!("-{}", packet.rssi)));
+            fields.push(("options", format!("{:02x}", packet.options)));
+            fields.push(("payload", hex(packet.data)));
+            if config.payload.format == PayloadFormat::Tlv {
+                tlv_fields(packet.data, &mut fields);
+            }
+            match reader::parse_sensor_values(packet.data, &config.payload) {
+                Ok(SensorValues { temperature, humidity, battery }) => {
+                    fields.push(("temperature", format!("{:.2}", temperature)));
+                    fields.push(("humidity", format!("{:.2}", humidity)));
+                    if let Some(battery) = battery {
+                        fields.push(("battery", battery.to_string()));
+                    }
+                }
+                Err(e) => fields.push(("sensor_error", e.label().to_string())),
+            }
+            if let Some(sequence) = reader::parse_sequence(packet.data, &config.payload) {
+                fields.push(("sequence", sequence.to_string()));
+            }
+        }),
+        ApiIdentifier::ModemStatus => xbee::parse_modem_status(buffer)
+            .map(|status| fields.push(("status", format!("{:?}", status)))),
+        ApiIdentifier::AtCommandResponse => xbee::parse_at_command_response(buffer).map(|response| {
+            fields.push(("frame_id", response.frame_id.to_string()));
+            fields.push(("command", String::from_utf8_lossy(&response.command).to_string()));
+            fields.push(("status", at_status_label(response.status).to_string()));
+            fields.push(("data", hex(response.data)));
+        }),
+        ApiIdentifier::TxStatus => xbee::parse_tx_status(buffer).map(|status| {
+            fields.push(("frame_id", status.frame_id.to_string()));
+            fields.push(("status", command::delivery_status_label(status.status).to_string()));
+        }),
+        ApiIdentifier::TxReq | ApiIdentifier::Tx16Req | ApiIdentifier::AtCommand => {
+            let length = xbee::frame_length(buffer).expect("frame validated");
+            if length < xbee::HEADER_LENGTH + 3 {
+                Err(xbee::Error::IncompleteFrame)
+            } else {
+                fields.push(("frame_id", buffer[xbee::HEADER_LENGTH + 1].to_string()));
+                request_fields(api_identifier, &buffer[xbee::HEADER_LENGTH + 2..length - 1], &mut fields);
+                Ok(())
+            }
+        }
+    };
+    if let Err(e) = parsed {
+        fields.push(("error", reader::xbee_error_label(&e).to_string()));
+    }
+    return fields;
+}
+
+/// Adds the header and the fields of a versioned payload that are not sensor values.
+fn tlv_fields(data: &[u8], fields: &mut Vec<(&'static str, String)>) {
+    let decoded = match payload::decode(data) {
+        Ok(decoded) => decoded,
+        Err(_) => return,
+    };
+    fields.push(("payload_version", decoded.header.version.to_string()));
+    fields.push(("node_type", format!("{:02x}", decoded.header.node_type)));
+    fields.push(("flags", format!("{:02x}", decoded.header.flags)));
+    for field in decoded.fields() {
+        match field {
+            payload::Field::Timestamp(timestamp) => fields.push(("node_timestamp", timestamp.to_string())),
+            payload::Field::ErrorCode(code) => fields.push(("error_code", format!("{:02x}", code))),
+            payload::Field::Unknown { kind, value } =>
+                fields.push(("unknown_field", format!("{:02x}:{}", kind, hex(value)))),
+            _ => (),
+        }
+    }
+}
+
+/// Adds the fields of an outgoing TX request or AT command after its frame id.
+fn request_fields(api_identifier: ApiIdentifier, rest: &[u8], fields: &mut Vec<(&'static str, String)>) {
+    let (name, prefix) = match api_identifier {
+        ApiIdentifier::Tx16Req => ("address", 2),
+        ApiIdentifier::TxReq => ("address", 8),
+        _ => ("command", 2),
+    };
+    if rest.len() < prefix + 1 {
+        fields.push(("data", hex(rest)));
+        return;
+    }
+    if name == "command" {
+        fields.push((name, String::from_utf8_lossy(&rest[..prefix]).to_string()));
+        fields.push(("parameter", hex(&rest[prefix..])));
+        return;
+    }
+    fields.push((name, hex(&rest[..prefix])));
+    fields.push(("options", format!("{:02x}", rest[prefix])));
+    fields.push(("data", hex(&rest[prefix + 1..])));
+}
+
+/// Formats the fields as one `key=value` line, e.g. for `listen`.
+pub fn line(fields: &[(&'static str, String)]) -> String {
+    return fields.iter()
+        .map(|(name, value)| format!("{}={}", name, value))
+        .collect::<Vec<String>>()
+        .join(" ");
+}
+
+/// Formats the fields as one aligned `key: value` line each, e.g. for `decode`.
+pub fn pretty(fields: &[(&'static str, String)]) -> String {
+    let width = fields.iter().map(|(name, _)| name.len()).max().unwrap_or_default();
+    return fields.iter()
+        .map(|(name, value)| format!("{:<width$}  {}", format!("{}:", name), value, width = width + 1))
+        .collect::<Vec<String>>()
+        .join("\n");
+}
+
+/// Parses a hex frame, ignoring whitespace and case, e.g. `7E 00 04 08 01 44 42 70` as copied from XCTU.
+pub fn parse_hex(value: &str) -> Option<Vec<u8>> {
+    let value: String = value.chars().filter(|c| !c.is_whitespace()).collect::<String>().to_lowercase();
+    return HexString::from_string(&value).ok().map(|hex| hex.as_bytes());
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn rx16() {
+        let mut config = Config::default();
+        config.payload.format = PayloadFormat::Raw;
+        let frame = parse_hex("7e 00 09 81 56 78 28 00 4e 85 68 3a 13").unwrap();
+        let actual = line(&fields(&frame, &config));
+        assert_eq!(actual, "frame=7e000981567828004e85683a13 api_identifier=rx16 address=5678 node=5678 rssi=-40 \
+            options=00 payload=4e85683a temperature=7.04 humidity=44.89");
+    }
+
+    #[test]
+    fn tlv() {
+        let mut config = Config::default();
+        config.payload.format = PayloadFormat::Tlv;
+        let frame = parse_hex("7e 00 12 81 56 78 28 00 01 01 00 2a 01 01 02 02 c0 02 02 11 89 f8").unwrap();
+        let actual = line(&fields(&frame, &config));
+        assert!(actual.ends_with("payload=0101002a01010202c002021189 payload_version=1 node_type=01 flags=01 \
+            temperature=7.04 humidity=44.89 sequence=42"), "{}", actual);
+    }
+
+    #[test]
+    fn tx_status() {
+        let actual = fields(&[0x7e, 0x00, 0x03, 0x89, 0x02, 0x01, 0x73], &Config::default());
+        assert_eq!(actual[2..], [("frame_id", "2".to_string()), ("status", "no_ack".to_string())]);
+    }
+
+    #[test]
+    fn requests() {
+        let actual = line(&fields(&[0x7e, 0x00, 0x06, 0x01, 0x02, 0x56, 0x78, 0x00, 0x01, 0x2d], &Config::default()));
+        assert_eq!(actual, "frame=7e00060102567800012d api_identifier=tx16_req frame_id=2 address=5678 options=00 data=01");
+        let actual = line(&fields(&[0x7e, 0x00, 0x05, 0x08, 0x52, 0x4e, 0x4a, 0xff, 0x0e], &Config::default()));
+        assert!(actual.ends_with("frame_id=82 command=NJ parameter=ff"), "{}", actual);
+    }
+
+    #[test]
+    fn invalid() {
+        let actual = fields(&[0x7e, 0x00, 0x02, 0x8a, 0x06, 0x6e], &Config::default());
+        assert_eq!(actual[1], ("error", "invalid_checksum".to_string()));
+        assert_eq!(pretty(&actual), "frame:  7e00028a066e\nerror:  invalid_checksum");
+    }
+}
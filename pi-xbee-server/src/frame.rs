@@ -0,0 +1,173 @@
+use std::io::{self, ErrorKind, Read};
+use log::warn;
+use crate::metrics::SKIPPED_BYTES_COUNTER;
+
+/// Longest frame data accepted; the largest valid RX frame carries about 110 bytes, so longer lengths are noise.
+const MAX_FRAME_DATA_LENGTH: usize = 128;
+
+/// Read timeouts after which a partial frame is dropped.
+const MAX_FRAME_TIMEOUTS: u32 = 3;
+
+/// Reads the next API frame, skipping bytes up to the start delimiter.
+///
+/// Frames with a length above [`MAX_FRAME_DATA_LENGTH`] are skipped, resynchronizing on the next start delimiter.
+/// Returns `None` if no frame started before the transport's read timeout, or if a frame stayed incomplete for
+/// [`MAX_FRAME_TIMEOUTS`] read timeouts.
+pub fn read_frame(transport: &mut dyn Read) -> io::Result<Option<Vec<u8>>> {
+    let mut buffer = vec![0x00u8; xbee::HEADER_LENGTH];
+    let mut filled = 0;
+    loop {
+        if filled == 0 {
+            if !read_delimiter(transport)? {
+                return Ok(None);
+            }
+            buffer[0] = xbee::START_DELIMITER;
+            filled = 1;
+        }
+        if !read_fully(transport, &mut buffer[filled..])? {
+            return Ok(None);
+        }
+        let data_length = u16::from_be_bytes([buffer[1], buffer[2]]) as usize;
+        if data_length <= MAX_FRAME_DATA_LENGTH {
+            break;
+        }
+        // A length byte may be the start delimiter of the next frame.
+        let next = buffer[1..].iter().position(|&byte| byte == xbee::START_DELIMITER)
+            .map_or(xbee::HEADER_LENGTH, |position| position + 1);
+        SKIPPED_BYTES_COUNTER.inc_by(next as u64);
+        warn!("uart skip frame too long; length={}", data_length);
+        buffer.copy_within(next.., 0);
+        filled = xbee::HEADER_LENGTH - next;
+    }
+    let frame_length = xbee::frame_length(&buffer).expect("header starts with delimiter");
+    buffer.resize(frame_length, 0x00);
+    if !read_fully(transport, &mut buffer[xbee::HEADER_LENGTH..])? {
+        return Ok(None);
+    }
+    return Ok(Some(buffer));
+}
+
+/// Skips bytes up to the start delimiter; returns `false` if none arrived before the read timeout.
+fn read_delimiter(transport: &mut dyn Read) -> io::Result<bool> {
+    let mut byte = [0x00u8];
+    loop {
+        match transport.read(&mut byte) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(_) if byte[0] == xbee::START_DELIMITER => return Ok(true),
+            Ok(_) => {
+                SKIPPED_BYTES_COUNTER.inc();
+                warn!("uart skip read one byte;");
+            }
+            Err(e) if is_timeout(&e) => return Ok(false),
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+}
+
+/// Like `Read::read_exact`, but keeps waiting when the transport times out within a frame, up to
+/// [`MAX_FRAME_TIMEOUTS`] times; returns `false` if it gave up.
+fn read_fully(transport: &mut dyn Read, buffer: &mut [u8]) -> io::Result<bool> {
+    let mut offset = 0;
+    let mut timeouts = 0;
+    while offset < buffer.len() {
+        match transport.read(&mut buffer[offset..]) {
+            Ok(0) => return Err(ErrorKind::UnexpectedEof.into()),
+            Ok(bytes_read) => offset += bytes_read,
+            Err(e) if is_timeout(&e) => {
+                timeouts += 1;
+                if timeouts >= MAX_FRAME_TIMEOUTS {
+                    SKIPPED_BYTES_COUNTER.inc_by(offset as u64);
+                    warn!("uart drop partial frame; missing={}", buffer.len() - offset);
+                    return Ok(false);
+                }
+            }
+            Err(e) if e.kind() == ErrorKind::Interrupted => (),
+            Err(e) => return Err(e),
+        }
+    }
+    return Ok(true);
+}
+
+fn is_timeout(e: &io::Error) -> bool {
+    return e.kind() == ErrorKind::TimedOut || e.kind() == ErrorKind::WouldBlock;
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+    use crate::transport::Loopback;
+    use super::*;
+
+    const FRAME: [u8; 13] = [0x7e, 0x00, 0x09, 0x81, 0x12, 0x34, 0x28, 0x00, 0x4e, 0x85, 0x68, 0x3a, 0x9b];
+
+    #[test]
+    fn frame() {
+        let mut loopback = Loopback::new();
+        loopback.write_all(&FRAME).unwrap();
+        let actual = read_frame(&mut loopback).unwrap();
+        assert_eq!(actual, Some(FRAME.to_vec()));
+    }
+
+    #[test]
+    fn resync() {
+        let mut loopback = Loopback::new();
+        loopback.write_all(&FRAME[5..]).unwrap();
+        loopback.write_all(&FRAME).unwrap();
+        let actual = read_frame(&mut loopback).unwrap();
+        assert_eq!(actual, Some(FRAME.to_vec()));
+    }
+
+    #[test]
+    fn too_long() {
+        let mut loopback = Loopback::new();
+        loopback.write_all(&[0x7e, 0x00, 0x81, 0x7e, 0x7e]).unwrap();
+        loopback.write_all(&FRAME).unwrap();
+        let actual = read_frame(&mut loopback).unwrap();
+        assert_eq!(actual, Some(FRAME.to_vec()));
+    }
+
+    /// Reads the chunks in turn, timing out after each.
+    struct Chunks(Vec<Vec<u8>>);
+
+    impl Read for Chunks {
+        fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+            return match self.0.first_mut() {
+                Some(chunk) if !chunk.is_empty() => {
+                    let length = buffer.len().min(chunk.len());
+                    buffer[..length].copy_from_slice(&chunk[..length]);
+                    chunk.drain(..length);
+                    Ok(length)
+                }
+                Some(_) => {
+                    self.0.remove(0);
+                    Err(ErrorKind::TimedOut.into())
+                }
+                None => Ok(0),
+            };
+        }
+    }
+
+    #[test]
+    fn partial_frame() {
+        let mut chunks = Chunks(vec![FRAME[..4].to_vec(), FRAME[4..6].to_vec(), vec![], FRAME.to_vec()]);
+        let actual = read_frame(&mut chunks).unwrap();
+        assert_eq!(actual, None);
+        let actual = read_frame(&mut chunks).unwrap();
+        assert_eq!(actual, Some(FRAME.to_vec()));
+    }
+
+    #[test]
+    fn no_frame() {
+        let mut loopback = Loopback::new();
+        let actual = read_frame(&mut loopback).unwrap();
+        assert_eq!(actual, None);
+    }
+
+    #[test]
+    fn end_of_stream() {
+        let mut stream: &[u8] = &FRAME[..6];
+        let actual = read_frame(&mut stream).unwrap_err();
+        assert_eq!(actual.kind(), ErrorKind::UnexpectedEof);
+    }
+}
@@ -0,0 +1,170 @@
+use std::time::{Duration, SystemTime};
+use serde::Serialize;
+use xbee::ModemStatus;
+use crate::config::HealthConfig;
+use crate::state::State;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Probe {
+    /// `/healthz`: the reader is connected and frames keep arriving.
+    Liveness,
+    /// `/readyz`: additionally, a frame was received and the coordinator is associated.
+    Readiness,
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub ok: bool,
+    /// Reasons why the probe fails.
+    pub failures: Vec<String>,
+    pub link: LinkReport,
+    pub last_frame_age_seconds: Option<u64>,
+    pub modem_status: Option<ModemStatusReport>,
+    pub nodes: Vec<NodeReport>,
+}
+
+#[derive(Serialize, Debug)]
+pub struct LinkReport {
+    pub up: bool,
+    pub error: Option<String>,
+    pub since_seconds: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct ModemStatusReport {
+    pub status: String,
+    pub age_seconds: u64,
+}
+
+#[derive(Serialize, Debug)]
+pub struct NodeReport {
+    pub address: String,
+    pub node: String,
+    pub last_seen_age_seconds: u64,
+    pub stale: bool,
+}
+
+fn age(now: SystemTime, time: SystemTime) -> Duration {
+    return now.duration_since(time).unwrap_or_default();
+}
+
+pub fn report(state: &State, config: &HealthConfig, probe: Probe, now: SystemTime) -> Report {
+    let mut failures = Vec::new();
+
+    let link = state.link();
+    if !link.up {
+        failures.push(format!("link down: {}", link.error.clone().unwrap_or_default()));
+    }
+
+    let last_frame = state.last_frame();
+    let frame_age = age(now, last_frame.unwrap_or(state.started));
+    if frame_age > Duration::from_secs(config.frame_timeout) {
+        failures.push(format!("no valid frame for {}s", frame_age.as_secs()));
+    }
+
+    let modem_status = state.modem_status();
+    if probe == Probe::Readiness {
+        if last_frame.is_none() {
+            failures.push("no valid frame received yet".to_string());
+        }
+        if let Some((status @ (ModemStatus::Disassociated | ModemStatus::SynchronizationLost), _)) = modem_status {
+            failures.push(format!("coordinator modem status {:?}", status));
+        }
+    }
+
+    let nodes = state.nodes().into_iter()
+        .map(|(address, node)| {
+            let last_seen_age = age(now, node.last_seen);
+            return NodeReport {
+                address,
+                node: node.name,
+                last_seen_age_seconds: last_seen_age.as_secs(),
+                stale: last_seen_age > Duration::from_secs(config.node_timeout),
+            };
+        })
+        .collect();
+
+    return Report {
+        ok: failures.is_empty(),
+        failures,
+        link: LinkReport {
+            up: link.up,
+            error: link.error,
+            since_seconds: age(now, link.since).as_secs(),
+        },
+        last_frame_age_seconds: last_frame.map(|time| age(now, time).as_secs()),
+        modem_status: modem_status.map(|(status, time)| ModemStatusReport {
+            status: format!("{:?}", status),
+            age_seconds: age(now, time).as_secs(),
+        }),
+        nodes,
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::reading::Reading;
+    use super::*;
+
+    fn seconds(seconds: u64) -> Duration {
+        return Duration::from_secs(seconds);
+    }
+
+    #[test]
+    fn link_down() {
+        let state = State::new();
+        let actual = report(&state, &HealthConfig::default(), Probe::Liveness, state.started);
+        assert!(!actual.ok);
+        assert_eq!(actual.failures, ["link down: not opened yet"]);
+    }
+
+    #[test]
+    fn starting() {
+        let state = State::new();
+        state.link_up();
+        let now = state.started + seconds(10);
+        assert!(report(&state, &HealthConfig::default(), Probe::Liveness, now).ok);
+        let actual = report(&state, &HealthConfig::default(), Probe::Readiness, now);
+        assert_eq!(actual.failures, ["no valid frame received yet"]);
+    }
+
+    #[test]
+    fn frames_stopped() {
+        let state = State::new();
+        state.link_up();
+        state.frame_received(state.started + seconds(10));
+        let config = HealthConfig { frame_timeout: 60, node_timeout: 30, ..HealthConfig::default() };
+        assert!(report(&state, &config, Probe::Readiness, state.started + seconds(70)).ok);
+        let actual = report(&state, &config, Probe::Liveness, state.started + seconds(71));
+        assert_eq!(actual.failures, ["no valid frame for 61s"]);
+        assert_eq!(actual.last_frame_age_seconds, Some(61));
+    }
+
+    #[test]
+    fn disassociated() {
+        let state = State::new();
+        state.link_up();
+        state.frame_received(state.started);
+        state.modem_status_received(ModemStatus::Disassociated, state.started);
+        assert!(report(&state, &HealthConfig::default(), Probe::Liveness, state.started).ok);
+        let actual = report(&state, &HealthConfig::default(), Probe::Readiness, state.started);
+        assert_eq!(actual.failures, ["coordinator modem status Disassociated"]);
+    }
+
+    #[test]
+    fn stale_node() {
+        let state = State::new();
+        state.link_up();
+        state.node_seen(&Reading::example("1234", "attic", state.started), state.started);
+        let now = state.started + seconds(20);
+        state.node_seen(&Reading::example("0013a20040647346", "cellar", now), now);
+        state.frame_received(state.started + seconds(20));
+        let config = HealthConfig { frame_timeout: 60, node_timeout: 30, ..HealthConfig::default() };
+        let actual = report(&state, &config, Probe::Readiness, state.started + seconds(40));
+        assert!(actual.ok);
+        let stale: Vec<(&str, bool)> = actual.nodes.iter()
+            .map(|node| (node.node.as_str(), node.stale))
+            .collect();
+        assert_eq!(stale, [("cellar", false), ("attic", true)]);
+    }
+}
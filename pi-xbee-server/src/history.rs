@@ -0,0 +1,219 @@
+use std::collections::{HashMap, VecDeque};
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+use log::warn;
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use crate::config::HistoryConfig;
+use crate::reading::Reading;
+use crate::state;
+
+/// Maximum number of points a query may return.
+pub const MAX_POINTS: u64 = 10_000;
+
+/// Running minimum, maximum and sum of the values within a bucket.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Aggregate {
+    min: f64,
+    max: f64,
+    sum: f64,
+}
+
+impl Aggregate {
+    fn new(value: f64) -> Aggregate {
+        return Aggregate { min: value, max: value, sum: value };
+    }
+
+    fn add(&mut self, other: &Aggregate) {
+        self.min = self.min.min(other.min);
+        self.max = self.max.max(other.max);
+        self.sum += other.sum;
+    }
+
+    fn summary(&self, count: u64) -> Summary {
+        return Summary { min: self.min, max: self.max, mean: self.sum / count as f64 };
+    }
+}
+
+/// Readings of one node within `resolution` seconds from `start`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+struct Bucket {
+    start: u64,
+    count: u64,
+    temperature: Aggregate,
+    humidity: Aggregate,
+    rssi: Aggregate,
+}
+
+impl Bucket {
+    fn new(start: u64, reading: &Reading) -> Bucket {
+        return Bucket {
+            start,
+            count: 1,
+            temperature: Aggregate::new(reading.temperature),
+            humidity: Aggregate::new(reading.humidity),
+            rssi: Aggregate::new(reading.rssi),
+        };
+    }
+
+    fn add(&mut self, other: &Bucket) {
+        self.count += other.count;
+        self.temperature.add(&other.temperature);
+        self.humidity.add(&other.humidity);
+        self.rssi.add(&other.rssi);
+    }
+
+    fn point(&self, step: u64) -> Point {
+        return Point {
+            start: self.start,
+            end: self.start + step,
+            count: self.count,
+            temperature: self.temperature.summary(self.count),
+            humidity: self.humidity.summary(self.count),
+            rssi: self.rssi.summary(self.count),
+        };
+    }
+}
+
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Summary {
+    pub min: f64,
+    pub max: f64,
+    pub mean: f64,
+}
+
+/// Aggregation of the readings taken from `start` (inclusive) to `end` (exclusive), in unix seconds.
+#[derive(Serialize, Clone, Copy, Debug, PartialEq)]
+pub struct Point {
+    pub start: u64,
+    pub end: u64,
+    pub count: u64,
+    pub temperature: Summary,
+    pub humidity: Summary,
+    pub rssi: Summary,
+}
+
+/// Per node readings aggregated into buckets of `resolution` seconds, kept for `retention` seconds.
+pub struct History {
+    resolution: u64,
+    retention: u64,
+    nodes: Mutex<HashMap<String, VecDeque<Bucket>>>,
+}
+
+impl History {
+    pub fn new(config: &HistoryConfig) -> History {
+        return History {
+            resolution: config.resolution,
+            retention: config.retention_days * 86_400,
+            nodes: Mutex::new(HashMap::new()),
+        };
+    }
+
+    /// Adds the reading to its bucket and drops the buckets of every node that fell out of the retention by `now`.
+    pub fn record(&self, reading: &Reading, now: SystemTime) {
+        let timestamp = reading.timestamp.max(0.0) as u64;
+        let bucket = Bucket::new(timestamp - timestamp % self.resolution, reading);
+        let mut nodes = self.nodes.lock().unwrap();
+        let buckets = nodes.entry(reading.address.clone()).or_default();
+        match buckets.binary_search_by_key(&bucket.start, |bucket| bucket.start) {
+            Ok(i) => buckets[i].add(&bucket),
+            Err(i) => buckets.insert(i, bucket),
+        }
+        // Like `Storage::prune`, drop what was taken before `now - retention`, that is whole buckets ending by then.
+        let now = state::unix_seconds(now) as u64;
+        for buckets in nodes.values_mut() {
+            while buckets.front().is_some_and(|bucket| bucket.start + self.resolution + self.retention <= now) {
+                buckets.pop_front();
+            }
+        }
+        nodes.retain(|_, buckets| !buckets.is_empty());
+    }
+
+    /// Rounds the step up to a multiple of the resolution.
+    pub fn step(&self, step: u64) -> u64 {
+        return step.max(1).div_ceil(self.resolution) * self.resolution;
+    }
+
+    /// Returns the readings of the node from `from` to `to` aggregated per `step` seconds,
+    /// leaving out empty steps, or `None` if the node has no history.
+    pub fn query(&self, address: &str, from: u64, to: u64, step: u64) -> Option<Vec<Point>> {
+        let step = self.step(step);
+        let nodes = self.nodes.lock().unwrap();
+        let mut merged: Vec<Bucket> = Vec::new();
+        for bucket in nodes.get(address)?.iter().filter(|bucket| bucket.start >= from && bucket.start <= to) {
+            let start = bucket.start - bucket.start % step;
+            match merged.last_mut() {
+                Some(last) if last.start == start => last.add(bucket),
+                _ => merged.push(Bucket { start, ..*bucket }),
+            }
+        }
+        return Some(merged.iter().map(|bucket| bucket.point(step)).collect());
+    }
+}
+
+/// Records the received readings in the history.
+pub async fn run(mut receiver: broadcast::Receiver<Reading>, history: Arc<History>) {
+    loop {
+        match receiver.recv().await {
+            Ok(reading) => history.record(&reading, SystemTime::now()),
+            Err(RecvError::Lagged(missed)) => warn!("history lagging; missed={}", missed),
+            Err(RecvError::Closed) => return,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+    use super::*;
+
+    fn history(retention_days: u64) -> History {
+        return History::new(&HistoryConfig { resolution: 60, retention_days, ..HistoryConfig::default() });
+    }
+
+    /// One reading every 10 seconds for two hours, temperature rising by 1 each minute from 0.
+    fn synthetic(history: &History, start: u64) {
+        for i in 0..720 {
+            let mut reading = Reading::example("abcd", "garage", SystemTime::UNIX_EPOCH);
+            reading.timestamp = (start + i * 10) as f64;
+            reading.temperature = (i / 6) as f64;
+            reading.humidity = if i % 2 == 0 { 40.0 } else { 60.0 };
+            history.record(&reading, SystemTime::UNIX_EPOCH + Duration::from_secs(start + i * 10));
+        }
+    }
+
+    #[test]
+    fn downsampling() {
+        let history = history(7);
+        synthetic(&history, 7_200);
+
+        let actual = history.query("abcd", 0, u64::MAX, 3_600).unwrap();
+        assert_eq!(actual.len(), 2);
+        assert_eq!((actual[0].start, actual[0].end, actual[0].count), (7_200, 10_800, 360));
+        assert_eq!(actual[0].temperature, Summary { min: 0.0, max: 59.0, mean: 29.5 });
+        assert_eq!(actual[1].temperature, Summary { min: 60.0, max: 119.0, mean: 89.5 });
+        assert_eq!(actual[1].humidity, Summary { min: 40.0, max: 60.0, mean: 50.0 });
+
+        let actual = history.query("abcd", 7_200, 7_319, 1).unwrap();
+        assert_eq!(actual.iter().map(|point| point.count).collect::<Vec<u64>>(), [6, 6]);
+        assert_eq!(actual[1].temperature, Summary { min: 1.0, max: 1.0, mean: 1.0 });
+        assert_eq!(history.step(90), 120);
+        assert!(history.query("1234", 0, u64::MAX, 60).is_none());
+    }
+
+    #[test]
+    fn retention() {
+        let history = history(1);
+        synthetic(&history, 0);
+        synthetic(&history, 86_400);
+        let actual = history.query("abcd", 0, u64::MAX, 3_600).unwrap();
+        assert_eq!(actual.iter().map(|point| point.start).collect::<Vec<u64>>(), [3_600, 86_400, 90_000]);
+        assert_eq!(actual[0].count, 6);
+
+        let mut reading = Reading::example("1234", "cellar", SystemTime::UNIX_EPOCH);
+        reading.timestamp = 180_000.0;
+        history.record(&reading, SystemTime::UNIX_EPOCH + Duration::from_secs(180_000));
+        assert!(history.query("abcd", 0, u64::MAX, 3_600).is_none());
+        assert_eq!(history.query("1234", 0, u64::MAX, 3_600).unwrap().len(), 1);
+    }
+}
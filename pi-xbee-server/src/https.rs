@@ -0,0 +1,21 @@
+use hyper::Client;
+use hyper::client::HttpConnector;
+use hyper_rustls::{HttpsConnector, HttpsConnectorBuilder};
+use log::warn;
+
+/// Client of the outputs posting over the network, speaking http and https.
+pub type HttpsClient = Client<HttpsConnector<HttpConnector>>;
+
+/// Returns a client trusting the system's root certificates, so a private CA can be added to them, or the bundled
+/// Mozilla roots if the system has none.
+pub fn client() -> HttpsClient {
+    let builder = match HttpsConnectorBuilder::new().with_native_roots() {
+        Ok(builder) => builder,
+        Err(e) => {
+            warn!("native root certificates unable to load, using the bundled ones; error={}", e);
+            HttpsConnectorBuilder::new().with_webpki_roots()
+        }
+    };
+    let connector = builder.https_or_http().enable_http1().build();
+    return Client::builder().build(connector);
+}
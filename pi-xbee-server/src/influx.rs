@@ -0,0 +1,282 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::Duration;
+use hyper::{Body, Method, Request};
+use log::{debug, info, warn};
+use tokio::io::AsyncWriteExt;
+use tokio::net::UdpSocket;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::time::Instant;
+use crate::config::{Config, InfluxConfig, InfluxOutput};
+use crate::https::{self, HttpsClient};
+use crate::metrics::INFLUX_DROPPED_COUNTER;
+use crate::reader::Backoff;
+use crate::reading::Reading;
+
+/// How long a write may take before it counts as failed.
+const WRITE_TIMEOUT: Duration = Duration::from_secs(10);
+/// Largest datagram of the `udp` output, small enough to avoid fragmentation.
+const MAX_DATAGRAM: usize = 1_400;
+
+fn escape(value: &str, special: &[char]) -> String {
+    let mut escaped = String::with_capacity(value.len());
+    for c in value.chars() {
+        if c == '\\' || special.contains(&c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    return escaped;
+}
+
+/// Formats the reading as a line of InfluxDB line protocol with a nanosecond timestamp.
+pub fn line(measurement: &str, reading: &Reading) -> String {
+    let mut line = format!("{},address={},node={} temperature={},humidity={},rssi={}",
+                           escape(measurement, &[',', ' ']),
+                           escape(&reading.address, &[',', '=', ' ']),
+                           escape(&reading.node, &[',', '=', ' ']),
+                           reading.temperature, reading.humidity, reading.rssi);
+    if let Some(battery) = reading.battery {
+        line.push_str(&format!(",battery={}", battery));
+    }
+    line.push_str(&format!(" {}", (reading.timestamp * 1e9) as u64));
+    return line;
+}
+
+/// Lines waiting to be written, bounded by dropping the oldest.
+struct Buffer {
+    lines: VecDeque<String>,
+    capacity: usize,
+}
+
+impl Buffer {
+    fn new(capacity: usize) -> Buffer {
+        return Buffer { lines: VecDeque::new(), capacity };
+    }
+
+    fn push(&mut self, line: String) {
+        if self.lines.len() == self.capacity {
+            self.lines.pop_front();
+            INFLUX_DROPPED_COUNTER.inc();
+        }
+        self.lines.push_back(line);
+    }
+
+    /// Returns the oldest lines up to the batch size, joined by newlines.
+    fn batch(&self, size: usize) -> (usize, String) {
+        let count = self.lines.len().min(size);
+        let mut body = String::new();
+        for line in self.lines.iter().take(count) {
+            body.push_str(line);
+            body.push('\n');
+        }
+        return (count, body);
+    }
+
+    /// Removes the oldest lines after they were written.
+    fn written(&mut self, count: usize) {
+        self.lines.drain(..count);
+    }
+}
+
+enum Output {
+    Http(HttpsClient),
+    File,
+    Udp(Option<UdpSocket>),
+}
+
+impl Output {
+    fn new(config: &InfluxConfig) -> Output {
+        return match config.output {
+            InfluxOutput::Http => Output::Http(https::client()),
+            InfluxOutput::File => Output::File,
+            InfluxOutput::Udp => Output::Udp(None),
+        };
+    }
+
+    async fn write(&mut self, config: &InfluxConfig, body: String) -> Result<(), String> {
+        return match tokio::time::timeout(WRITE_TIMEOUT, self.write_body(config, body)).await {
+            Ok(result) => result,
+            Err(_) => Err(format!("timed out after {:?}", WRITE_TIMEOUT)),
+        };
+    }
+
+    async fn write_body(&mut self, config: &InfluxConfig, body: String) -> Result<(), String> {
+        match self {
+            Output::Http(client) => {
+                let mut request = Request::builder().method(Method::POST).uri(&config.url);
+                if let Some(token) = &config.token {
+                    request = request.header("Authorization", format!("Token {}", token));
+                }
+                let request = request.body(Body::from(body)).map_err(|e| e.to_string())?;
+                let response = client.request(request).await.map_err(|e| e.to_string())?;
+                if !response.status().is_success() {
+                    return Err(format!("status {}", response.status()));
+                }
+            }
+            Output::File => {
+                let mut file = tokio::fs::OpenOptions::new().create(true).append(true).open(&config.path).await
+                    .map_err(|e| e.to_string())?;
+                file.write_all(body.as_bytes()).await.map_err(|e| e.to_string())?;
+                file.flush().await.map_err(|e| e.to_string())?;
+            }
+            Output::Udp(socket) => {
+                if socket.is_none() {
+                    let udp = UdpSocket::bind("0.0.0.0:0").await.map_err(|e| e.to_string())?;
+                    udp.connect(&config.address).await.map_err(|e| e.to_string())?;
+                    *socket = Some(udp);
+                }
+                let socket = socket.as_ref().expect("socket connected");
+                let mut datagram = String::new();
+                for line in body.lines() {
+                    if !datagram.is_empty() && datagram.len() + line.len() + 1 > MAX_DATAGRAM {
+                        socket.send(datagram.as_bytes()).await.map_err(|e| e.to_string())?;
+                        datagram.clear();
+                    }
+                    datagram.push_str(line);
+                    datagram.push('\n');
+                }
+                socket.send(datagram.as_bytes()).await.map_err(|e| e.to_string())?;
+            }
+        }
+        return Ok(());
+    }
+}
+
+/// Writes the received readings in batches, keeping them buffered and retrying with backoff while the output fails.
+///
+/// Once the receiver is closed, the buffered lines are written one last time.
+pub async fn run(config: Arc<Config>, mut receiver: broadcast::Receiver<Reading>) {
+    let influx = &config.influx;
+    let mut output = Output::new(influx);
+    let mut buffer = Buffer::new(influx.buffer_size);
+    let mut backoff = Backoff::new();
+    let mut interval = tokio::time::interval(Duration::from_secs(influx.flush_interval));
+    let mut retry_at: Option<Instant> = None;
+    loop {
+        let (flush, closed) = tokio::select! {
+            received = receiver.recv() => match received {
+                Ok(reading) => {
+                    buffer.push(line(&influx.measurement, &reading));
+                    (buffer.lines.len() >= influx.batch_size, false)
+                }
+                Err(RecvError::Lagged(missed)) => {
+                    warn!("influx sink lagging; missed={}", missed);
+                    (false, false)
+                }
+                Err(RecvError::Closed) => (true, true),
+            },
+            _ = interval.tick() => (true, false),
+        };
+        if !flush || (!closed && retry_at.is_some_and(|time| Instant::now() < time)) {
+            continue;
+        }
+        while !buffer.lines.is_empty() {
+            let (count, body) = buffer.batch(influx.batch_size);
+            match output.write(influx, body).await {
+                Ok(()) => {
+                    debug!("influx lines written; count={}", count);
+                    buffer.written(count);
+                    backoff.reset();
+                    retry_at = None;
+                }
+                Err(e) => {
+                    let delay = backoff.next();
+                    warn!("influx write failed; output={:?} error={} buffered={} delay={:?}",
+                        influx.output, e, buffer.lines.len(), delay);
+                    retry_at = Some(Instant::now() + delay);
+                    if let Output::Udp(socket) = &mut output {
+                        *socket = None;
+                    }
+                    break;
+                }
+            }
+        }
+        if closed {
+            if !buffer.lines.is_empty() {
+                warn!("influx lines dropped on shutdown; count={}", buffer.lines.len());
+                INFLUX_DROPPED_COUNTER.inc_by(buffer.lines.len() as u64);
+            }
+            info!("influx sink stopped;");
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+    use tokio::io::AsyncReadExt;
+    use tokio::net::TcpListener;
+    use super::*;
+
+    #[test]
+    fn line_protocol() {
+        let mut reading = Reading::example("abcd", "garage, north", SystemTime::UNIX_EPOCH);
+        reading.timestamp = 1_700_000_000.5;
+        assert_eq!(line("xbee", &reading),
+                   r"xbee,address=abcd,node=garage\,\ north temperature=21.5,humidity=40,rssi=-40 1700000000500000000");
+        reading.battery = Some(87.0);
+        assert!(line("xbee", &reading).contains(",rssi=-40,battery=87 "));
+    }
+
+    #[test]
+    fn buffer() {
+        let mut buffer = Buffer::new(3);
+        for line in ["a", "b", "c", "d"] {
+            buffer.push(line.to_string());
+        }
+        assert_eq!(buffer.batch(2), (2, "b\nc\n".to_string()));
+        buffer.written(2);
+        assert_eq!(buffer.batch(2), (1, "d\n".to_string()));
+    }
+
+    /// Accepts one HTTP request, answers it with the status and returns the request.
+    async fn respond(listener: &TcpListener, status: &str) -> String {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut chunk = [0x00u8; 1024];
+        while !String::from_utf8_lossy(&request).contains("xbee,") {
+            let length = stream.read(&mut chunk).await.unwrap();
+            request.extend_from_slice(&chunk[..length]);
+        }
+        stream.write_all(format!("HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status).as_bytes())
+            .await.unwrap();
+        return String::from_utf8(request).unwrap();
+    }
+
+    #[tokio::test]
+    async fn http_retry() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::default();
+        config.influx.url = format!("http://{}/write", listener.local_addr().unwrap());
+        config.influx.token = Some("secret".to_string());
+        config.influx.batch_size = 1;
+        config.influx.flush_interval = 1;
+        let (sender, receiver) = broadcast::channel(4);
+        tokio::spawn(run(Arc::new(config), receiver));
+        sender.send(Reading::example("abcd", "garage", SystemTime::UNIX_EPOCH)).unwrap();
+
+        let request = respond(&listener, "503 Service Unavailable").await;
+        assert!(request.starts_with("POST /write HTTP/1.1\r\n"), "{}", request);
+        assert!(request.to_lowercase().contains("authorization: token secret"), "{}", request);
+        let request = respond(&listener, "204 No Content").await;
+        assert!(request.ends_with("xbee,address=abcd,node=garage temperature=21.5,humidity=40,rssi=-40 0\n"), "{}", request);
+    }
+
+    #[tokio::test]
+    async fn flush_on_close() {
+        let path = std::env::temp_dir().join(format!("pi-xbee-influx-{}.lp", std::process::id()));
+        let mut config = Config::default();
+        config.influx.output = InfluxOutput::File;
+        config.influx.path = path.clone();
+        config.influx.flush_interval = 3_600;
+        let (sender, receiver) = broadcast::channel(4);
+        sender.send(Reading::example("abcd", "garage", SystemTime::UNIX_EPOCH)).unwrap();
+        drop(sender);
+        run(Arc::new(config), receiver).await;
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written, "xbee,address=abcd,node=garage temperature=21.5,humidity=40,rssi=-40 0\n");
+    }
+}
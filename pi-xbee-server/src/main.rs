@@ -1,79 +1,161 @@
-use rppal::uart::{Parity, Uart};
-use std::time::Duration;
-use htu21;
+mod alerts;
+mod api;
+mod auth;
+mod cli;
+mod command;
+mod config;
+mod dashboard;
+mod decode;
+mod frame;
+mod health;
+mod history;
+mod https;
+mod influx;
+mod metrics;
+mod mqtt;
+mod push;
+mod reader;
+mod reading;
+mod sequence;
+mod shutdown;
+mod staleness;
+mod state;
+mod storage;
+mod stream;
+mod systemd;
+mod transport;
+
+use std::process;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, SystemTime};
+use clap::Parser;
+use futures_util::future;
 use log::{info, warn};
-use lazy_static::lazy_static;
-use prometheus::{TextEncoder, Encoder, register_gauge, opts, labels, Gauge};
-use warp::{Filter, http};
+use sd_notify::NotifyState;
+use tokio::sync::{oneshot, watch};
+use prometheus::{TextEncoder, Encoder};
+use warp::{Filter, Rejection, http};
 use warp::http::header::CONTENT_TYPE;
-use hex_string::HexString;
-
-lazy_static! {
-    static ref TEMPERATURE_GAUGE: Gauge = register_gauge!(opts!(
-        "pi_xbee_temperature_celcius",
-        "Temperature in celcius.",
-        labels! {"handler" => "all",}
-    ))
-    .unwrap();
-    static ref HUMIDITY_GAUGE: Gauge = register_gauge!(opts!(
-        "pi_xbee_humidity",
-        "Humidity.",
-        labels! {"handler" => "all",}
-    ))
-    .unwrap();
-}
+use crate::alerts::Alerts;
+use crate::auth::Auth;
+use crate::config::{Args, Config, Mode, Scope};
+use crate::health::Probe;
+use crate::history::History;
+use crate::state::State;
+use crate::storage::Storage;
+
+/// How long the reader, the sinks and open HTTP connections may take in total to finish once a signal arrived.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
 
-struct SensorValues {
-    temperature: f64,
-    humidity: f64,
+/// Passes requests only if the feature behind the route is enabled.
+fn enabled(enabled: bool) -> impl Filter<Extract=(), Error=Rejection> + Clone {
+    return warp::any()
+        .and_then(move || async move {
+            if enabled {
+                return Ok(());
+            }
+            return Err(warp::reject::not_found());
+        })
+        .untuple_one();
 }
 
 #[tokio::main]
 async fn main() {
-    env_logger::init();
-
-    tokio::spawn(async {
-        let mut uart = Uart::new(57_600, Parity::None, 8, 1)
-            .expect("unable to create UART");
-        uart.set_read_mode(13, Duration::default())
-            .expect("unable to set baud rate");
-        let mut buffer = [0x00u8; 13];
-        loop {
-            let bytes_read = uart.read(&mut buffer).expect("unable to read");
-            if bytes_read > 0 && buffer[0] == 0x7e {
-                let mut buffer_vec = Vec::new();
-                for byte in buffer {
-                    buffer_vec.push(byte);
-                }
-                let buffer_string = HexString::from_bytes(&buffer_vec).as_string();
-                match parse_sensor_values(buffer.as_slice()) {
-                    Ok(SensorValues { temperature, humidity }) => {
-                        TEMPERATURE_GAUGE.set(temperature as f64);
-                        HUMIDITY_GAUGE.set(humidity as f64);
-                        info!("sensor data received; buffer={} temperature={} humidity={}", buffer_string, temperature, humidity);
-                    }
-                    Err(e) => {
-                        warn!("sensor data invalid; buffer={} error={:?}", buffer_string, e);
-                    }
-                }
-            } else {
-                warn!("uart skip read one byte;");
-                let mut skip_buffer = [0x00u8];
-                uart.read(&mut skip_buffer).expect("unable to skip read");
+    let args = Args::parse();
+    let config = match Config::load(&args) {
+        Ok(config) => Arc::new(config),
+        Err(e) => {
+            eprintln!("configuration invalid; {}", e);
+            process::exit(2);
+        }
+    };
+    env_logger::Builder::new()
+        .filter_level(config.log_level().expect("log level validated"))
+        .parse_default_env()
+        .init();
+    let code = match &args.mode {
+        None | Some(Mode::Serve) => {
+            serve(config).await;
+            0
+        }
+        Some(Mode::Decode { frame }) => cli::decode(&config, frame),
+        Some(Mode::Listen) => cli::listen(&config),
+        Some(Mode::Send(send)) => cli::send(&config, send).await,
+        Some(Mode::Replay { capture }) => cli::replay(&config, capture),
+    };
+    process::exit(code);
+}
+
+async fn serve(config: Arc<Config>) {
+    let bind_address = config.bind_address().expect("bind address validated");
+
+    let state = Arc::new(State::with_recent_readings(config.api.recent_readings));
+    let mut sinks = Vec::new();
+
+    let storage = if config.sinks.sqlite {
+        match Storage::open(&config.sqlite.path) {
+            Ok(storage) => Some(Arc::new(Mutex::new(storage))),
+            Err(e) => {
+                eprintln!("sqlite unable to open; path={} error={}", config.sqlite.path.display(), e);
+                process::exit(1);
             }
         }
+    } else {
+        None
+    };
+    if let Some(storage) = &storage {
+        info!("sqlite sink enabled; path={} retention_days={}", config.sqlite.path.display(), config.sqlite.retention_days);
+        let storage_config = config.clone();
+        let receiver = state.subscribe();
+        let storage = storage.clone();
+        sinks.push(tokio::task::spawn_blocking(move || storage::run(storage_config, receiver, storage)));
+    }
+
+    if config.sinks.mqtt {
+        info!("mqtt sink enabled; host={} port={} topic={}", config.mqtt.host, config.mqtt.port, config.mqtt.topic);
+        sinks.push(tokio::spawn(mqtt::run(config.clone(), state.subscribe())));
     }
-    );
-
-    fn parse_sensor_values(buffer: &[u8]) -> Result<SensorValues, htu21::Error> {
-        let data_part = buffer.split_at(8).1.split_at(4).0;
-        let split = data_part.split_at(2);
-        let temperature = htu21::parse_temperature(split.0)? as f64;
-        let humidity = htu21::parse_humidity(split.1)? as f64;
-        return Ok(SensorValues { temperature, humidity });
+
+    if config.sinks.influx {
+        info!("influx sink enabled; output={:?}", config.influx.output);
+        sinks.push(tokio::spawn(influx::run(config.clone(), state.subscribe())));
+    }
+
+    let (shutdown, shutdown_received) = watch::channel(false);
+    if config.push.enabled {
+        info!("push enabled; output={:?} url={} interval={}s",
+            config.push.output, config.push.url, config.push.interval);
+        sinks.push(tokio::spawn(push::run(config.clone(), shutdown_received)));
     }
 
+    let alerts = Arc::new(Alerts::new(config.alerts.clone()));
+    if !config.alerts.is_empty() {
+        info!("alerts enabled; rules={} notifiers={}", config.alerts.len(), config.notifiers.len());
+        sinks.push(tokio::spawn(alerts::run(config.clone(), state.subscribe(), alerts.clone())));
+    }
+
+    let history = Arc::new(History::new(&config.history));
+    if config.history.enabled {
+        if let Some(storage) = &storage {
+            let retention = Duration::from_secs(config.history.retention_days * 86_400);
+            let now = SystemTime::now();
+            let from = now.checked_sub(retention).map_or(0.0, state::unix_seconds);
+            if let Err(e) = storage.lock().unwrap().for_each_since(from, |reading| history.record(&reading, now)) {
+                warn!("history unable to load from sqlite; error={}", e);
+            }
+        }
+        tokio::spawn(history::run(state.subscribe(), history.clone()));
+    }
+
+    let reader_config = config.clone();
+    let reader_state = state.clone();
+    let reader = tokio::task::spawn_blocking(move || reader::run(reader_config, reader_state));
+    tokio::spawn(staleness::run(config.clone(), state.clone()));
+
+    let auth = Arc::new(Auth::new(&config));
+    let metrics_open = !(config.auth.enabled && config.auth.protect_metrics);
     let metrics = warp::path!("metrics")
+        .and(auth::require(auth.clone(), Scope::Read, metrics_open))
         .map(|| {
             let encoder = TextEncoder::new();
             let metric_families = prometheus::gather();
@@ -87,9 +169,79 @@ async fn main() {
             return response;
         });
 
-    let routes = warp::get().and(metrics);
+    let metrics = enabled(config.sinks.prometheus).and(metrics);
+
+    let healthz = warp::path!("healthz")
+        .map(|| Probe::Liveness);
+    let readyz = warp::path!("readyz")
+        .map(|| Probe::Readiness);
+    let api = enabled(config.api.enabled).and(api::routes(state.clone(), storage, auth.clone()));
+    let stream = enabled(config.api.enabled).and(stream::route(state.clone(), auth.clone()));
+    let alerts = enabled(config.api.enabled).and(api::alerts(alerts, auth.clone()));
+    let history = enabled(config.api.enabled && config.history.enabled).and(api::history(history, auth.clone()));
+    let dashboard = enabled(config.api.enabled && config.api.dashboard).and(dashboard::route(auth.clone()));
+    let commands = enabled(config.commands.enabled).and(api::commands(state.clone(), &config.commands, auth));
+
+    let health_config = config.clone();
+    let health_state = state.clone();
+    let health = healthz.or(readyz).unify()
+        .map(move |probe| {
+            let report = health::report(&health_state, &health_config.health, probe, SystemTime::now());
+            let status = if report.ok {
+                http::StatusCode::OK
+            } else {
+                http::StatusCode::SERVICE_UNAVAILABLE
+            };
+            return warp::reply::with_status(warp::reply::json(&report), status);
+        });
+
+    let routes = warp::get().and(metrics.or(health).or(api).or(history).or(stream).or(alerts).or(dashboard))
+        .or(commands)
+        .recover(auth::recover);
+
+    let (stop_server, server_stopped) = oneshot::channel::<()>();
+    let server_stopped = async {
+        let _ = server_stopped.await;
+    };
+    let server = match (&config.http.tls_cert, &config.http.tls_key) {
+        (Some(cert), Some(key)) => warp::serve(routes).tls().cert_path(cert).key_path(key)
+            .try_bind_with_graceful_shutdown(bind_address, server_stopped)
+            .map(|(_, server)| tokio::spawn(server))
+            .map_err(|e| e.to_string()),
+        _ => warp::serve(routes).try_bind_with_graceful_shutdown(bind_address, server_stopped)
+            .map(|(_, server)| tokio::spawn(server))
+            .map_err(|e| e.to_string()),
+    };
+    let server = match server {
+        Ok(server) => server,
+        Err(e) => {
+            eprintln!("http server unable to bind; bind={} error={}", bind_address, e);
+            process::exit(1);
+        }
+    };
+    info!("http server listening; bind={} tls={} auth={}",
+        bind_address, config.http.tls_cert.is_some(), config.auth.enabled);
+    systemd::notify(&[NotifyState::Ready]);
+    if let Some(interval) = systemd::watchdog_interval() {
+        tokio::spawn(systemd::watchdog(interval, config.clone(), state.clone()));
+    }
 
-    warp::serve(routes)
-        .run(([0, 0, 0, 0], 8080))
-        .await;
+    let signal = shutdown::signal().await;
+    info!("shutdown started; signal={}", signal);
+    systemd::notify(&[NotifyState::Stopping]);
+    tokio::spawn(shutdown::force_on_signal());
+    let deadline = tokio::time::Instant::now() + SHUTDOWN_TIMEOUT;
+    state.stop();
+    // The reader may be blocked opening the transport or reading a frame; it is abandoned at the deadline.
+    if tokio::time::timeout_at(deadline, reader).await.is_err() {
+        warn!("reader did not stop; timeout={:?}", SHUTDOWN_TIMEOUT);
+    }
+    state.close();
+    let _ = shutdown.send(true);
+    let _ = stop_server.send(());
+    let stopped = future::join(future::join_all(sinks), server);
+    if tokio::time::timeout_at(deadline, stopped).await.is_err() {
+        warn!("shutdown timed out; timeout={:?}", SHUTDOWN_TIMEOUT);
+    }
+    info!("shutdown complete;");
 }
@@ -0,0 +1,146 @@
+use lazy_static::lazy_static;
+use prometheus::{register_gauge, register_gauge_vec, register_histogram_vec, register_int_counter,
+                 register_int_counter_vec, histogram_opts, opts, Gauge, GaugeVec, HistogramVec, IntCounter,
+                 IntCounterVec};
+
+lazy_static! {
+    pub static ref TEMPERATURE_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_temperature_celcius", "Temperature in celcius."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref HUMIDITY_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_humidity", "Humidity."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref DEW_POINT_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_dew_point_celcius", "Dew point in celcius."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref ABSOLUTE_HUMIDITY_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_absolute_humidity_grams_per_cubic_meter", "Absolute humidity in g/m³."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref VAPOR_PRESSURE_DEFICIT_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_vapor_pressure_deficit_kilopascals", "Vapor pressure deficit in kPa."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref HEAT_INDEX_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_heat_index_celcius", "Heat index (apparent temperature) in celcius."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref BATTERY_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_battery_percent", "Battery level of the node, if its payload carries one."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref LAST_SEEN_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_last_seen_timestamp_seconds", "Unix time of the last reading of the node."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref NODE_STALE_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_node_stale", "Whether the node sent no reading within the node timeout (1) or did (0)."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref RSSI_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_rssi_dbm", "Received signal strength of the last packet of the node."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref RSSI_HISTOGRAM: HistogramVec = register_histogram_vec!(
+        histogram_opts!(
+            "pi_xbee_packet_rssi_dbm",
+            "Received signal strength of the packets of the node.",
+            vec![-100.0, -95.0, -90.0, -85.0, -80.0, -75.0, -70.0, -60.0, -50.0, -40.0]
+        ),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref COORDINATOR_RSSI_GAUGE: Gauge = register_gauge!(opts!(
+        "pi_xbee_coordinator_rssi_dbm",
+        "Received signal strength of the last packet as reported by the coordinator's DB command."
+    ))
+    .unwrap();
+    pub static ref LINK_UP_GAUGE: Gauge = register_gauge!(opts!(
+        "pi_xbee_link_up",
+        "Whether the transport to the XBee module is open (1) or being reopened (0)."
+    ))
+    .unwrap();
+    pub static ref MQTT_CONNECTED_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_mqtt_connected", "Whether the MQTT client is connected to the broker (1) or reconnecting (0)."),
+        &["client_id"]
+    )
+    .unwrap();
+    pub static ref INFLUX_DROPPED_COUNTER: IntCounter = register_int_counter!(opts!(
+        "pi_xbee_influx_dropped_lines_total",
+        "Line protocol lines dropped because the buffer was full while the output failed."
+    ))
+    .unwrap();
+    pub static ref PUSHES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        opts!("pi_xbee_pushes_total", "Pushes of the metrics to the Pushgateway or remote write endpoint by status."),
+        &["status"]
+    )
+    .unwrap();
+    pub static ref PUSH_DROPPED_COUNTER: IntCounter = register_int_counter!(opts!(
+        "pi_xbee_push_dropped_snapshots_total",
+        "Remote write snapshots dropped because the buffer was full while the endpoint failed."
+    ))
+    .unwrap();
+    pub static ref ALERT_FIRING_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_alert_firing", "Whether the alert rule fires for the node (1) or not (0)."),
+        &["alert", "address", "node"]
+    )
+    .unwrap();
+    pub static ref COMMANDS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        opts!("pi_xbee_commands_total", "Commands sent to nodes by TX Status, or timeout if none arrived."),
+        &["status"]
+    )
+    .unwrap();
+    pub static ref FRAMES_COUNTER: IntCounterVec = register_int_counter_vec!(
+        opts!("pi_xbee_frames_total", "Frames with a valid checksum by API identifier."),
+        &["api_identifier"]
+    )
+    .unwrap();
+    pub static ref FRAME_ERRORS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        opts!("pi_xbee_frame_errors_total", "Frames dropped by error, e.g. invalid_checksum."),
+        &["error"]
+    )
+    .unwrap();
+    pub static ref SKIPPED_BYTES_COUNTER: IntCounter = register_int_counter!(opts!(
+        "pi_xbee_skipped_bytes_total",
+        "Bytes skipped while resynchronizing on the start delimiter."
+    ))
+    .unwrap();
+    pub static ref NODE_PACKETS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        opts!("pi_xbee_node_packets_total", "Receive packets by source node."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref PACKETS_LOST_COUNTER: IntCounterVec = register_int_counter_vec!(
+        opts!("pi_xbee_node_packets_lost_total", "Packets of the node missing from its sequence numbers."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref PACKETS_DUPLICATE_COUNTER: IntCounterVec = register_int_counter_vec!(
+        opts!("pi_xbee_node_packets_duplicate_total", "Packets of the node repeating the previous sequence number."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref PACKET_LOSS_GAUGE: GaugeVec = register_gauge_vec!(
+        opts!("pi_xbee_node_packet_loss_ratio", "Share of the packets of the node lost since the server started."),
+        &["address", "node"]
+    )
+    .unwrap();
+    pub static ref SENSOR_ERRORS_COUNTER: IntCounterVec = register_int_counter_vec!(
+        opts!("pi_xbee_sensor_errors_total", "Receive packets without valid sensor values by source node and error."),
+        &["address", "node", "error"]
+    )
+    .unwrap();
+}
@@ -0,0 +1,262 @@
+use std::collections::HashSet;
+use std::sync::Arc;
+use std::time::Duration;
+use log::{info, warn};
+use rumqttc::{AsyncClient, Event, EventLoop, MqttOptions, Outgoing, Packet, QoS};
+use serde::Serialize;
+use tokio::sync::broadcast::{self, error::RecvError};
+use tokio::task::JoinHandle;
+use crate::config::{Config, MqttConfig};
+use crate::metrics::MQTT_CONNECTED_GAUGE;
+use crate::reader::Backoff;
+use crate::reading::Reading;
+
+/// Number of publishes queued while the broker is unreachable.
+const QUEUE_CAPACITY: usize = 64;
+/// `rumqttc` rejects keep alive intervals below 5 seconds.
+const MIN_KEEP_ALIVE: u64 = 5;
+/// How long the queued publishes may take to be sent on disconnect, the broker may be unreachable.
+const DISCONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+
+fn options(config: &MqttConfig, client_id: &str) -> MqttOptions {
+    let mut options = MqttOptions::new(client_id, &config.host, config.port);
+    options.set_keep_alive(Duration::from_secs(config.keep_alive.max(MIN_KEEP_ALIVE)));
+    if let Some(username) = &config.username {
+        options.set_credentials(username, config.password.clone().unwrap_or_default());
+    }
+    return options;
+}
+
+pub fn qos(qos: u8) -> QoS {
+    return match qos {
+        0 => QoS::AtMostOnce,
+        1 => QoS::AtLeastOnce,
+        _ => QoS::ExactlyOnce,
+    };
+}
+
+/// Fills in the topic template, replacing the MQTT wildcards and level separator within the node name.
+pub fn topic(template: &str, reading: &Reading, value: &str) -> String {
+    let node = reading.node.replace(['/', '+', '#'], "_");
+    return template
+        .replace("{node}", &node)
+        .replace("{address}", &reading.address)
+        .replace("{value}", value);
+}
+
+/// Home Assistant entities of a node: value, name, device class, unit and entity category.
+const ENTITIES: [(&str, &str, &str, &str, Option<&str>); 4] = [
+    ("temperature", "Temperature", "temperature", "°C", None),
+    ("humidity", "Humidity", "humidity", "%", None),
+    ("rssi", "RSSI", "signal_strength", "dBm", Some("diagnostic")),
+    ("battery", "Battery", "battery", "%", Some("diagnostic")),
+];
+
+/// Home Assistant MQTT discovery config of a sensor entity.
+#[derive(Serialize, Debug)]
+struct Discovery<'a> {
+    name: &'a str,
+    unique_id: String,
+    state_topic: String,
+    device_class: &'a str,
+    unit_of_measurement: &'a str,
+    state_class: &'a str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    entity_category: Option<&'a str>,
+    /// Seconds without a value after which Home Assistant shows the entity as unavailable.
+    expire_after: u64,
+    device: Device<'a>,
+}
+
+#[derive(Serialize, Debug)]
+struct Device<'a> {
+    identifiers: [String; 1],
+    name: &'a str,
+    manufacturer: &'a str,
+    model: &'a str,
+}
+
+/// Returns the topics and payloads of the discovery config of the node's entities.
+pub fn discovery(config: &Config, reading: &Reading) -> Vec<(String, String)> {
+    let id = format!("pi_xbee_{}", reading.address);
+    return ENTITIES.iter()
+        .filter(|(value, ..)| *value != "battery" || config.payload.carries_battery())
+        .map(|&(value, name, device_class, unit, entity_category)| {
+            let discovery = Discovery {
+                name,
+                unique_id: format!("{}_{}", id, value),
+                state_topic: topic(&config.mqtt.topic, reading, value),
+                device_class,
+                unit_of_measurement: unit,
+                state_class: "measurement",
+                entity_category,
+                expire_after: config.health.node_timeout,
+                device: Device { identifiers: [id.clone()], name: &reading.node, manufacturer: "Digi", model: "XBee" },
+            };
+            let topic = format!("{}/sensor/{}/{}/config", config.mqtt.discovery_prefix, id, value);
+            return (topic, serde_json::to_string(&discovery).expect("discovery serializable"));
+        })
+        .collect();
+}
+
+/// Returns a client of the broker that keeps reconnecting in the background.
+pub fn client(config: Arc<Config>, client_id: &str) -> (AsyncClient, JoinHandle<()>) {
+    let (client, eventloop) = AsyncClient::new(options(&config.mqtt, client_id), QUEUE_CAPACITY);
+    let connection = tokio::spawn(connect(config, client_id.to_string(), eventloop));
+    return (client, connection);
+}
+
+/// Publishes the received readings, one message per value, and disconnects once the receiver is closed.
+pub async fn run(config: Arc<Config>, mut receiver: broadcast::Receiver<Reading>) {
+    let (client, mut connection) = client(config.clone(), &config.mqtt.client_id);
+    let mut discovered = HashSet::new();
+    loop {
+        let reading = match receiver.recv().await {
+            Ok(reading) => reading,
+            Err(RecvError::Lagged(missed)) => {
+                warn!("mqtt sink lagging; missed={}", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => {
+                let disconnected = client.try_disconnect().is_ok()
+                    && tokio::time::timeout(DISCONNECT_TIMEOUT, &mut connection).await.is_ok();
+                if !disconnected {
+                    connection.abort();
+                    MQTT_CONNECTED_GAUGE.with_label_values(&[&config.mqtt.client_id]).set(0.0);
+                }
+                info!("mqtt sink stopped;");
+                return;
+            }
+        };
+        if config.mqtt.discovery && discovered.insert(reading.address.clone()) {
+            info!("mqtt discovery published; address={} node={}", reading.address, reading.node);
+            for (topic, payload) in discovery(&config, &reading) {
+                if let Err(e) = client.publish(topic, qos(config.mqtt.qos), true, payload).await {
+                    warn!("mqtt discovery publish failed; address={} error={}", reading.address, e);
+                }
+            }
+        }
+        let values = [("temperature", Some(reading.temperature)), ("humidity", Some(reading.humidity)),
+                      ("rssi", Some(reading.rssi)), ("battery", reading.battery)];
+        for (name, value) in values.into_iter().filter_map(|(name, value)| Some((name, value?))) {
+            let topic = topic(&config.mqtt.topic, &reading, name);
+            if let Err(e) = client.publish(topic, qos(config.mqtt.qos), config.mqtt.retain, value.to_string()).await {
+                warn!("mqtt publish failed; address={} error={}", reading.address, e);
+            }
+        }
+    }
+}
+
+/// Drives the connection to the broker, reconnecting with backoff whenever it fails, until it disconnected.
+async fn connect(config: Arc<Config>, client_id: String, mut eventloop: EventLoop) {
+    let connected = MQTT_CONNECTED_GAUGE.with_label_values(&[&client_id]);
+    let mut backoff = Backoff::new();
+    loop {
+        match eventloop.poll().await {
+            Ok(Event::Outgoing(Outgoing::Disconnect)) => {
+                connected.set(0.0);
+                return;
+            }
+            Ok(Event::Incoming(Packet::ConnAck(_))) => {
+                info!("mqtt connected; host={} port={} client_id={}", config.mqtt.host, config.mqtt.port, client_id);
+                connected.set(1.0);
+                backoff.reset();
+            }
+            Ok(_) => (),
+            Err(e) => {
+                connected.set(0.0);
+                let delay = backoff.next();
+                warn!("mqtt connection failed; host={} port={} client_id={} error={} delay={:?}",
+                    config.mqtt.host, config.mqtt.port, client_id, e, delay);
+                tokio::time::sleep(delay).await;
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::{TcpListener, TcpStream};
+    use crate::config::PayloadFormat;
+    use super::*;
+
+    /// Reads one MQTT control packet, returning its fixed header byte and its body.
+    async fn read_packet(stream: &mut TcpStream) -> (u8, Vec<u8>) {
+        let header = stream.read_u8().await.unwrap();
+        let (mut length, mut shift) = (0usize, 0);
+        loop {
+            let byte = stream.read_u8().await.unwrap();
+            length |= ((byte & 0x7f) as usize) << shift;
+            shift += 7;
+            if byte & 0x80 == 0 {
+                break;
+            }
+        }
+        let mut body = vec![0x00u8; length];
+        stream.read_exact(&mut body).await.unwrap();
+        return (header, body);
+    }
+
+    #[test]
+    fn topics() {
+        let reading = Reading::example("abcd", "garage/north", SystemTime::UNIX_EPOCH);
+        assert_eq!(topic("xbee/{node}/{value}", &reading, "temperature"), "xbee/garage_north/temperature");
+        assert_eq!(topic("home/{address}/{value}", &reading, "rssi"), "home/abcd/rssi");
+    }
+
+    #[test]
+    fn home_assistant_discovery() {
+        let mut config = Config::default();
+        config.payload.format = PayloadFormat::Raw;
+        let reading = Reading::example("abcd", "garage", SystemTime::UNIX_EPOCH);
+        let actual = discovery(&config, &reading);
+        assert_eq!(actual.len(), 3);
+        assert_eq!(actual[0].0, "homeassistant/sensor/pi_xbee_abcd/temperature/config");
+        assert_eq!(actual[0].1, concat!(
+            r#"{"name":"Temperature","unique_id":"pi_xbee_abcd_temperature","state_topic":"xbee/garage/temperature","#,
+            r#""device_class":"temperature","unit_of_measurement":"°C","state_class":"measurement","expire_after":1800,"#,
+            r#""device":{"identifiers":["pi_xbee_abcd"],"name":"garage","manufacturer":"Digi","model":"XBee"}}"#));
+        assert!(actual[2].1.contains(r#""entity_category":"diagnostic""#));
+
+        config.payload.battery_offset = Some(4);
+        let actual = discovery(&config, &reading);
+        assert_eq!(actual[3].0, "homeassistant/sensor/pi_xbee_abcd/battery/config");
+
+        let actual = discovery(&Config::default(), &reading);
+        assert_eq!(actual.len(), 4);
+        assert_eq!(actual[3].0, "homeassistant/sensor/pi_xbee_abcd/battery/config");
+    }
+
+    #[tokio::test]
+    async fn publish() {
+        let broker = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::default();
+        config.mqtt.port = broker.local_addr().unwrap().port();
+        config.mqtt.host = "127.0.0.1".to_string();
+        config.mqtt.retain = true;
+        let (sender, receiver) = broadcast::channel(4);
+        tokio::spawn(run(Arc::new(config), receiver));
+        sender.send(Reading::example("abcd", "garage", SystemTime::UNIX_EPOCH)).unwrap();
+
+        let (mut stream, _) = broker.accept().await.unwrap();
+        let (header, _) = read_packet(&mut stream).await;
+        assert_eq!(header, 0x10);
+        stream.write_all(&[0x20, 0x02, 0x00, 0x00]).await.unwrap();
+        let mut messages = Vec::new();
+        for _ in 0..3 {
+            let (header, body) = read_packet(&mut stream).await;
+            assert_eq!(header, 0x31, "qos 0 publish with retain");
+            let length = u16::from_be_bytes([body[0], body[1]]) as usize;
+            let topic = String::from_utf8(body[2..2 + length].to_vec()).unwrap();
+            let payload = String::from_utf8(body[2 + length..].to_vec()).unwrap();
+            messages.push((topic, payload));
+        }
+        assert_eq!(messages, [
+            ("xbee/garage/temperature".to_string(), "21.5".to_string()),
+            ("xbee/garage/humidity".to_string(), "40".to_string()),
+            ("xbee/garage/rssi".to_string(), "-40".to_string()),
+        ]);
+    }
+}
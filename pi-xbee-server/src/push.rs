@@ -0,0 +1,316 @@
+use std::collections::VecDeque;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use hyper::{Body, Method, Request};
+use log::{debug, info, warn};
+use prometheus::{Encoder, TextEncoder};
+use prometheus::proto::{MetricFamily, MetricType};
+use prost::Message;
+use tokio::sync::watch;
+use crate::config::{Config, PushConfig, PushOutput};
+use crate::https::{self, HttpsClient};
+use crate::metrics::{PUSHES_COUNTER, PUSH_DROPPED_COUNTER};
+
+/// How long a push may take before it counts as failed.
+const PUSH_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Messages of the Prometheus remote write protocol, version 1.
+#[derive(Clone, PartialEq, Message)]
+pub struct WriteRequest {
+    #[prost(message, repeated, tag = "1")]
+    pub timeseries: Vec<TimeSeries>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct TimeSeries {
+    /// Sorted by name, including `__name__`.
+    #[prost(message, repeated, tag = "1")]
+    pub labels: Vec<Label>,
+    #[prost(message, repeated, tag = "2")]
+    pub samples: Vec<Sample>,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Label {
+    #[prost(string, tag = "1")]
+    pub name: String,
+    #[prost(string, tag = "2")]
+    pub value: String,
+}
+
+#[derive(Clone, PartialEq, Message)]
+pub struct Sample {
+    #[prost(double, tag = "1")]
+    pub value: f64,
+    /// Unix time in milliseconds.
+    #[prost(int64, tag = "2")]
+    pub timestamp: i64,
+}
+
+/// Converts the metric families into remote write series, one per counter, gauge and histogram bucket, sum and count.
+pub fn series(families: &[MetricFamily], extra: &[(&str, &str)], timestamp: i64) -> Vec<TimeSeries> {
+    let mut series = Vec::new();
+    for family in families {
+        let name = family.get_name();
+        for metric in family.get_metric() {
+            let labels: Vec<(&str, &str)> = metric.get_label().iter()
+                .map(|label| (label.get_name(), label.get_value()))
+                .chain(extra.iter().copied())
+                .collect();
+            let mut push = |suffix: &str, label: Option<(&str, String)>, value: f64| {
+                let mut labels: Vec<Label> = labels.iter()
+                    .map(|(name, value)| Label { name: name.to_string(), value: value.to_string() })
+                    .chain(label.map(|(name, value)| Label { name: name.to_string(), value }))
+                    .collect();
+                labels.push(Label { name: "__name__".to_string(), value: format!("{}{}", name, suffix) });
+                labels.sort_by(|a, b| a.name.cmp(&b.name));
+                series.push(TimeSeries { labels, samples: vec![Sample { value, timestamp }] });
+            };
+            match family.get_field_type() {
+                MetricType::COUNTER => push("", None, metric.get_counter().get_value()),
+                MetricType::GAUGE => push("", None, metric.get_gauge().get_value()),
+                MetricType::UNTYPED => push("", None, metric.get_untyped().get_value()),
+                MetricType::HISTOGRAM => {
+                    let histogram = metric.get_histogram();
+                    for bucket in histogram.get_bucket() {
+                        let le = Some(("le", bucket.get_upper_bound().to_string()));
+                        push("_bucket", le, bucket.get_cumulative_count() as f64);
+                    }
+                    let count = histogram.get_sample_count() as f64;
+                    push("_bucket", Some(("le", "+Inf".to_string())), count);
+                    push("_sum", None, histogram.get_sample_sum());
+                    push("_count", None, count);
+                }
+                MetricType::SUMMARY => {
+                    let summary = metric.get_summary();
+                    for quantile in summary.get_quantile() {
+                        push("", Some(("quantile", quantile.get_quantile().to_string())), quantile.get_value());
+                    }
+                    push("_sum", None, summary.get_sample_sum());
+                    push("_count", None, summary.get_sample_count() as f64);
+                }
+            }
+        }
+    }
+    return series;
+}
+
+/// Encodes the series as a snappy compressed remote write request.
+pub fn remote_write_body(series: Vec<TimeSeries>) -> Vec<u8> {
+    let request = WriteRequest { timeseries: series }.encode_to_vec();
+    return snap::raw::Encoder::new().compress_vec(&request).expect("remote write request compressible");
+}
+
+/// Pushgateway URL of the grouping key.
+fn pushgateway_url(push: &PushConfig) -> String {
+    let mut url = format!("{}/metrics/job/{}", push.url.trim_end_matches('/'), push.job);
+    if let Some(instance) = &push.instance {
+        url.push_str(&format!("/instance/{}", instance));
+    }
+    return url;
+}
+
+/// Takes a snapshot of the metrics as the body of a push.
+fn snapshot(push: &PushConfig, time: SystemTime) -> Vec<u8> {
+    let families = prometheus::gather();
+    return match push.output {
+        PushOutput::Pushgateway => {
+            let mut buffer = Vec::new();
+            TextEncoder::new().encode(&families, &mut buffer).expect("metrics encodable");
+            buffer
+        }
+        PushOutput::RemoteWrite => {
+            let mut extra = vec![("job", push.job.as_str())];
+            if let Some(instance) = &push.instance {
+                extra.push(("instance", instance.as_str()));
+            }
+            let timestamp = time.duration_since(UNIX_EPOCH).unwrap_or_default().as_millis() as i64;
+            remote_write_body(series(&families, &extra, timestamp))
+        }
+    };
+}
+
+async fn send(client: &HttpsClient, push: &PushConfig, body: Vec<u8>) -> Result<(), String> {
+    let request = match push.output {
+        PushOutput::Pushgateway => Request::builder()
+            .method(Method::PUT)
+            .uri(pushgateway_url(push))
+            .header("Content-Type", TextEncoder::new().format_type()),
+        PushOutput::RemoteWrite => Request::builder()
+            .method(Method::POST)
+            .uri(&push.url)
+            .header("Content-Type", "application/x-protobuf")
+            .header("Content-Encoding", "snappy")
+            .header("X-Prometheus-Remote-Write-Version", "0.1.0"),
+    };
+    let request = match &push.token {
+        Some(token) => request.header("Authorization", format!("Bearer {}", token)),
+        None => request,
+    };
+    let request = request.body(Body::from(body)).map_err(|e| e.to_string())?;
+    let response = match tokio::time::timeout(PUSH_TIMEOUT, client.request(request)).await {
+        Ok(response) => response.map_err(|e| e.to_string())?,
+        Err(_) => return Err(format!("timed out after {:?}", PUSH_TIMEOUT)),
+    };
+    if !response.status().is_success() {
+        return Err(format!("status {}", response.status()));
+    }
+    return Ok(());
+}
+
+/// Snapshots waiting to be pushed, bounded by dropping the oldest.
+struct Buffer {
+    snapshots: VecDeque<Vec<u8>>,
+    capacity: usize,
+}
+
+impl Buffer {
+    fn push(&mut self, snapshot: Vec<u8>) {
+        if self.snapshots.len() == self.capacity {
+            self.snapshots.pop_front();
+            PUSH_DROPPED_COUNTER.inc();
+        }
+        self.snapshots.push_back(snapshot);
+    }
+}
+
+/// Pushes a snapshot of the metrics every interval and once more on shutdown.
+///
+/// The Pushgateway only keeps the latest push, so only the latest snapshot is kept while it fails; remote write
+/// snapshots carry their timestamp and are all kept, up to the buffer size, and pushed oldest first.
+pub async fn run(config: Arc<Config>, mut shutdown: watch::Receiver<bool>) {
+    let push = &config.push;
+    let client = https::client();
+    let capacity = if push.output == PushOutput::Pushgateway { 1 } else { push.buffer_size };
+    let mut buffer = Buffer { snapshots: VecDeque::new(), capacity };
+    let mut interval = tokio::time::interval(Duration::from_secs(push.interval));
+    loop {
+        let closed = tokio::select! {
+            _ = shutdown.changed() => true,
+            _ = interval.tick() => false,
+        };
+        if push.output == PushOutput::Pushgateway {
+            buffer.snapshots.clear();
+        }
+        buffer.push(snapshot(push, SystemTime::now()));
+        while let Some(snapshot) = buffer.snapshots.front() {
+            match send(&client, push, snapshot.clone()).await {
+                Ok(()) => {
+                    PUSHES_COUNTER.with_label_values(&["success"]).inc();
+                    buffer.snapshots.pop_front();
+                }
+                Err(e) => {
+                    PUSHES_COUNTER.with_label_values(&["failure"]).inc();
+                    warn!("push failed; output={:?} url={} error={} buffered={}",
+                        push.output, push.url, e, buffer.snapshots.len());
+                    break;
+                }
+            }
+        }
+        debug!("push done; output={:?} buffered={}", push.output, buffer.snapshots.len());
+        if closed {
+            info!("push stopped; buffered={}", buffer.snapshots.len());
+            return;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use prometheus::{Counter, HistogramOpts, HistogramVec, Opts, Registry};
+    use tokio::io::{AsyncReadExt, AsyncWriteExt};
+    use tokio::net::TcpListener;
+    use super::*;
+
+    fn label(series: &TimeSeries, name: &str) -> String {
+        return series.labels.iter().find(|label| label.name == name).map(|label| label.value.clone()).unwrap();
+    }
+
+    #[test]
+    fn remote_write_series() {
+        let registry = Registry::new();
+        let counter = Counter::with_opts(Opts::new("frames_total", "Frames.")).unwrap();
+        let histogram = HistogramVec::new(HistogramOpts::new("rssi", "RSSI.").buckets(vec![-80.0, -40.0]), &["node"])
+            .unwrap();
+        registry.register(Box::new(counter.clone())).unwrap();
+        registry.register(Box::new(histogram.clone())).unwrap();
+        counter.inc_by(3.0);
+        histogram.with_label_values(&["garage"]).observe(-50.0);
+
+        let actual = series(&registry.gather(), &[("job", "pi")], 1_000);
+        assert_eq!(actual[0].labels, [Label { name: "__name__".to_string(), value: "frames_total".to_string() },
+                                      Label { name: "job".to_string(), value: "pi".to_string() }]);
+        assert_eq!(actual[0].samples, [Sample { value: 3.0, timestamp: 1_000 }]);
+        let names: Vec<String> = actual[1..].iter().map(|series| label(series, "__name__")).collect();
+        assert_eq!(names, ["rssi_bucket", "rssi_bucket", "rssi_bucket", "rssi_sum", "rssi_count"]);
+        let buckets: Vec<(String, f64)> = actual[1..4].iter()
+            .map(|series| (label(series, "le"), series.samples[0].value))
+            .collect();
+        assert_eq!(buckets, [("-80".to_string(), 0.0), ("-40".to_string(), 1.0), ("+Inf".to_string(), 1.0)]);
+        assert_eq!(label(&actual[4], "node"), "garage");
+
+        let body = remote_write_body(actual.clone());
+        let decoded = WriteRequest::decode(snap::raw::Decoder::new().decompress_vec(&body).unwrap().as_slice());
+        assert_eq!(decoded.unwrap().timeseries, actual);
+    }
+
+    #[test]
+    fn grouping_key() {
+        let mut push = PushConfig { url: "http://gateway:9091/".to_string(), ..PushConfig::default() };
+        assert_eq!(pushgateway_url(&push), "http://gateway:9091/metrics/job/pi-xbee-server");
+        push.instance = Some("garage".to_string());
+        assert_eq!(pushgateway_url(&push), "http://gateway:9091/metrics/job/pi-xbee-server/instance/garage");
+    }
+
+    /// Accepts one HTTP request, answers it with the status and returns its head and body.
+    async fn respond(listener: &TcpListener, status: &str) -> (String, Vec<u8>) {
+        let (mut stream, _) = listener.accept().await.unwrap();
+        let mut request = Vec::new();
+        let mut chunk = [0x00u8; 4096];
+        loop {
+            let length = stream.read(&mut chunk).await.unwrap();
+            request.extend_from_slice(&chunk[..length]);
+            let text = String::from_utf8_lossy(&request).to_lowercase();
+            if let Some(end) = text.find("\r\n\r\n") {
+                let content_length = text.lines()
+                    .find_map(|line| line.strip_prefix("content-length: "))
+                    .map_or(0, |value| value.trim().parse::<usize>().unwrap());
+                if request.len() >= end + 4 + content_length {
+                    stream.write_all(format!("HTTP/1.1 {}\r\ncontent-length: 0\r\nconnection: close\r\n\r\n", status)
+                        .as_bytes()).await.unwrap();
+                    let body = request.split_off(end + 4);
+                    return (String::from_utf8(request).unwrap(), body);
+                }
+            }
+        }
+    }
+
+    #[tokio::test]
+    async fn remote_write_buffered() {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let mut config = Config::default();
+        config.push.output = PushOutput::RemoteWrite;
+        config.push.url = format!("http://{}/api/v1/write", listener.local_addr().unwrap());
+        config.push.token = Some("secret".to_string());
+        config.push.interval = 1;
+        PUSH_DROPPED_COUNTER.inc_by(0);
+        let (_shutdown, shutdown_received) = watch::channel(false);
+        tokio::spawn(run(Arc::new(config), shutdown_received));
+
+        let (head, _) = respond(&listener, "503 Service Unavailable").await;
+        assert!(head.starts_with("POST /api/v1/write HTTP/1.1\r\n"), "{}", head);
+        let head = head.to_lowercase();
+        assert!(head.contains("authorization: bearer secret") && head.contains("content-encoding: snappy"), "{}", head);
+        let mut timestamps = Vec::new();
+        for _ in 0..2 {
+            let (_, body) = respond(&listener, "204 No Content").await;
+            let request = WriteRequest::decode(snap::raw::Decoder::new().decompress_vec(&body).unwrap().as_slice())
+                .unwrap();
+            assert!(request.timeseries.iter().all(|series| label(series, "job") == "pi-xbee-server"));
+            let names: Vec<String> = request.timeseries.iter().map(|series| label(series, "__name__")).collect();
+            assert!(names.contains(&"pi_xbee_push_dropped_snapshots_total".to_string()), "{:?}", names);
+            timestamps.push(request.timeseries[0].samples[0].timestamp);
+        }
+        assert!(timestamps[0] < timestamps[1], "{:?}", timestamps);
+    }
+}
@@ -0,0 +1,595 @@
+use std::fmt;
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant, SystemTime};
+use hex_string::HexString;
+use log::{debug, info, warn};
+use crate::config::{Config, PayloadConfig, PayloadFormat};
+use crate::frame;
+use crate::metrics::{ABSOLUTE_HUMIDITY_GAUGE, BATTERY_GAUGE, COORDINATOR_RSSI_GAUGE, DEW_POINT_GAUGE, FRAMES_COUNTER,
+                     FRAME_ERRORS_COUNTER, HEAT_INDEX_GAUGE, HUMIDITY_GAUGE, NODE_PACKETS_COUNTER,
+                     PACKETS_DUPLICATE_COUNTER, PACKETS_LOST_COUNTER, PACKET_LOSS_GAUGE, RSSI_GAUGE, RSSI_HISTOGRAM,
+                     SENSOR_ERRORS_COUNTER, TEMPERATURE_GAUGE, VAPOR_PRESSURE_DEFICIT_GAUGE};
+use crate::reading::{Frame, Reading};
+use crate::sequence::Gap;
+use crate::staleness;
+use crate::state::{self, State};
+use crate::transport::{self, Transport};
+use xbee::ApiIdentifier;
+
+const MIN_BACKOFF: Duration = Duration::from_secs(1);
+const MAX_BACKOFF: Duration = Duration::from_secs(60);
+/// How often a reader waiting to reopen the transport checks whether it was stopped.
+const STOP_POLL_INTERVAL: Duration = Duration::from_millis(100);
+/// Frame id of the `DB` queries, a non-zero id requests a response.
+const RSSI_FRAME_ID: u8 = 0x01;
+
+#[derive(Debug)]
+pub struct SensorValues {
+    pub temperature: f64,
+    pub humidity: f64,
+    pub battery: Option<f64>,
+}
+
+#[derive(Debug)]
+pub enum Error {
+    Xbee(xbee::Error),
+    Htu21(htu21::Error),
+    InvalidPayloadLength(usize),
+    InvalidCrc,
+    Payload(payload::Error),
+    /// The versioned payload carries an error code of the node instead of both values.
+    NodeReported(u8),
+    /// The versioned payload carries neither both values nor an error code.
+    MissingValues,
+}
+
+impl Error {
+    /// Value of the `error` label of the error counters.
+    pub fn label(&self) -> &'static str {
+        return match self {
+            Error::Xbee(e) => xbee_error_label(e),
+            Error::Htu21(htu21::Error::NotTemperatureSensoreValue) => "not_temperature_sensor_value",
+            Error::Htu21(htu21::Error::NotHumiditySensorValue) => "not_humidity_sensor_value",
+            Error::InvalidPayloadLength(_) => "invalid_payload_length",
+            Error::InvalidCrc => "invalid_crc",
+            Error::Payload(payload::Error::UnsupportedVersion(_)) => "unsupported_payload_version",
+            Error::Payload(payload::Error::InvalidLength { .. }) => "invalid_field_length",
+            Error::Payload(_) => "truncated_payload",
+            Error::NodeReported(_) => "node_error",
+            Error::MissingValues => "missing_values",
+        };
+    }
+}
+
+pub fn xbee_error_label(e: &xbee::Error) -> &'static str {
+    return match e {
+        xbee::Error::NoStartDelimiter => "no_start_delimiter",
+        xbee::Error::IncompleteFrame => "incomplete_frame",
+        xbee::Error::InvalidChecksum => "invalid_checksum",
+        xbee::Error::UnsupportedApiIdentifier(_) => "unsupported_api_identifier",
+        xbee::Error::ParameterTooLong(_) => "parameter_too_long",
+        xbee::Error::DataTooLong(_) => "data_too_long",
+    };
+}
+
+pub fn api_identifier_label(api_identifier: ApiIdentifier) -> &'static str {
+    return match api_identifier {
+        ApiIdentifier::TxReq => "tx_req",
+        ApiIdentifier::Tx16Req => "tx16_req",
+        ApiIdentifier::TxStatus => "tx_status",
+        ApiIdentifier::AtCommand => "at_command",
+        ApiIdentifier::AtCommandResponse => "at_command_response",
+        ApiIdentifier::Rx64 => "rx64",
+        ApiIdentifier::Rx16 => "rx16",
+        ApiIdentifier::ModemStatus => "modem_status",
+    };
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Xbee(e) => write!(f, "xbee frame invalid: {:?}", e),
+            Error::Htu21(e) => write!(f, "htu21 value invalid: {:?}", e),
+            Error::InvalidPayloadLength(length) => write!(f, "payload length invalid: {}", length),
+            Error::InvalidCrc => write!(f, "htu21 crc invalid"),
+            Error::Payload(e) => write!(f, "payload invalid: {:?}", e),
+            Error::NodeReported(code) => write!(f, "node reported error: {:#04x}", code),
+            Error::MissingValues => write!(f, "payload misses temperature or humidity"),
+        }
+    }
+}
+
+impl From<xbee::Error> for Error {
+    fn from(value: xbee::Error) -> Self {
+        return Error::Xbee(value);
+    }
+}
+
+impl From<htu21::Error> for Error {
+    fn from(value: htu21::Error) -> Self {
+        return Error::Htu21(value);
+    }
+}
+
+impl From<payload::Error> for Error {
+    fn from(value: payload::Error) -> Self {
+        return Error::Payload(value);
+    }
+}
+
+/// Doubles the delay between reopen attempts up to a maximum.
+pub struct Backoff {
+    delay: Duration,
+}
+
+impl Backoff {
+    pub fn new() -> Backoff {
+        return Backoff { delay: MIN_BACKOFF };
+    }
+
+    pub fn next(&mut self) -> Duration {
+        let delay = self.delay;
+        self.delay = (self.delay * 2).min(MAX_BACKOFF);
+        return delay;
+    }
+
+    pub fn reset(&mut self) {
+        self.delay = MIN_BACKOFF;
+    }
+}
+
+/// Reads frames from the transport until stopped, reopening it with backoff whenever it fails.
+pub fn run(config: Arc<Config>, state: Arc<State>) {
+    let mut backoff = Backoff::new();
+    while !state.stopping() {
+        match transport::open(&config.serial) {
+            Ok(mut transport) => {
+                info!("transport opened; transport={:?} device={} address={}",
+                    config.serial.transport, config.serial.device, config.serial.address);
+                state.link_up();
+                match read_frames(&mut transport, &config, &state, &mut backoff) {
+                    Ok(()) => break,
+                    Err(e) => {
+                        warn!("transport failed; error={}", e);
+                        state.link_down(e.to_string());
+                    }
+                }
+            }
+            Err(e) => {
+                warn!("transport unable to open; transport={:?} error={}", config.serial.transport, e);
+                state.link_down(e.to_string());
+            }
+        }
+        let delay = backoff.next();
+        info!("transport reopen scheduled; delay={:?}", delay);
+        let reopen_at = Instant::now() + delay;
+        while !state.stopping() && Instant::now() < reopen_at {
+            thread::sleep(STOP_POLL_INTERVAL.min(reopen_at - Instant::now()));
+        }
+    }
+    info!("reader stopped;");
+}
+
+/// Handles frames until the transport fails, returning the failure, or the reader is stopped.
+fn read_frames(transport: &mut Box<dyn Transport>, config: &Config, state: &State,
+               backoff: &mut Backoff) -> std::io::Result<()> {
+    let rssi_interval = Duration::from_secs(config.coordinator.rssi_interval);
+    let mut last_rssi_query: Option<Instant> = None;
+    while !state.stopping() {
+        if !rssi_interval.is_zero() && last_rssi_query.is_none_or(|time| time.elapsed() >= rssi_interval) {
+            last_rssi_query = Some(Instant::now());
+            query_rssi(transport)?;
+        }
+        write_commands(transport, state)?;
+        let buffer = match frame::read_frame(transport)? {
+            Some(buffer) => buffer,
+            None => continue,
+        };
+        backoff.reset();
+        handle_frame(&buffer, config, state);
+    }
+    return Ok(());
+}
+
+/// Asks the coordinator for the RSSI of the last packet it received.
+fn query_rssi(transport: &mut Box<dyn Transport>) -> std::io::Result<()> {
+    let at_command = xbee::AtCommand::new(RSSI_FRAME_ID, *b"DB", &[]).expect("DB takes no parameter");
+    transport.write_all(at_command.as_slice())?;
+    return transport.flush();
+}
+
+/// Writes the TX requests of the commands queued since the last call.
+fn write_commands(transport: &mut Box<dyn Transport>, state: &State) -> std::io::Result<()> {
+    let queued = state.transmitter().take_queued();
+    if queued.is_empty() {
+        return Ok(());
+    }
+    for tx_request in queued {
+        transport.write_all(&tx_request)?;
+    }
+    return transport.flush();
+}
+
+fn handle_frame(buffer: &[u8], config: &Config, state: &State) {
+    let now = SystemTime::now();
+    let buffer_string = HexString::from_bytes(&buffer.to_vec()).as_string();
+    let api_identifier = match xbee::api_identifier(buffer) {
+        Ok(api_identifier) => api_identifier,
+        Err(e) => {
+            FRAME_ERRORS_COUNTER.with_label_values(&[xbee_error_label(&e)]).inc();
+            warn!("frame invalid; buffer={} error={:?}", buffer_string, e);
+            return;
+        }
+    };
+    state.frame_received(now);
+    FRAMES_COUNTER.with_label_values(&[api_identifier_label(api_identifier)]).inc();
+    state.publish_frame(Frame {
+        timestamp: state::unix_seconds(now),
+        api_identifier: api_identifier_label(api_identifier),
+        frame: buffer_string.clone(),
+    });
+    match api_identifier {
+        ApiIdentifier::Rx64 | ApiIdentifier::Rx16 => {
+            handle_sensor_frame(buffer, &buffer_string, config, state, now);
+        }
+        ApiIdentifier::ModemStatus => {
+            match xbee::parse_modem_status(buffer) {
+                Ok(status) => {
+                    info!("modem status received; buffer={} status={:?}", buffer_string, status);
+                    state.modem_status_received(status, now);
+                }
+                Err(e) => warn!("modem status invalid; buffer={} error={:?}", buffer_string, e),
+            }
+        }
+        ApiIdentifier::AtCommandResponse => {
+            match xbee::parse_at_command_response(buffer) {
+                Ok(xbee::AtCommandResponse { command, status: 0, data: [rssi, ..], .. }) if &command == b"DB" => {
+                    debug!("coordinator rssi received; buffer={} rssi={}", buffer_string, rssi);
+                    COORDINATOR_RSSI_GAUGE.set(-(*rssi as f64));
+                }
+                Ok(response) => warn!("at command response unexpected; buffer={} response={:?}", buffer_string, response),
+                Err(e) => warn!("at command response invalid; buffer={} error={:?}", buffer_string, e),
+            }
+        }
+        ApiIdentifier::TxStatus => {
+            match xbee::parse_tx_status(buffer) {
+                Ok(status) => {
+                    debug!("tx status received; buffer={} frame_id={} status={:?}", buffer_string, status.frame_id, status.status);
+                    if !state.transmitter().status_received(&status) {
+                        warn!("tx status unexpected; buffer={} frame_id={}", buffer_string, status.frame_id);
+                    }
+                }
+                Err(e) => warn!("tx status invalid; buffer={} error={:?}", buffer_string, e),
+            }
+        }
+        api_identifier => {
+            warn!("frame unexpected; buffer={} api_identifier={:?}", buffer_string, api_identifier);
+        }
+    }
+}
+
+fn handle_sensor_frame(buffer: &[u8], buffer_string: &str, config: &Config, state: &State, now: SystemTime) {
+    let packet = match xbee::parse_rx_packet(buffer) {
+        Ok(packet) => packet,
+        Err(e) => {
+            FRAME_ERRORS_COUNTER.with_label_values(&[xbee_error_label(&e)]).inc();
+            warn!("receive packet invalid; buffer={} error={:?}", buffer_string, e);
+            return;
+        }
+    };
+    let address = HexString::from_bytes(&packet.source.as_slice().to_vec()).as_string();
+    let node = config.node_name(&address);
+    NODE_PACKETS_COUNTER.with_label_values(&[&address, node]).inc();
+    let rssi = -(packet.rssi as f64);
+    RSSI_GAUGE.with_label_values(&[&address, node]).set(rssi);
+    RSSI_HISTOGRAM.with_label_values(&[&address, node]).observe(rssi);
+    if let Some(sequence) = parse_sequence(packet.data, &config.payload) {
+        if !sequence_received(state, [&address, node], sequence) {
+            return;
+        }
+    }
+    match parse_sensor_values(packet.data, &config.payload) {
+        Ok(SensorValues { temperature, humidity, battery }) => {
+            let reading = Reading {
+                address: address.clone(),
+                node: node.to_string(),
+                timestamp: state::unix_seconds(now),
+                temperature,
+                humidity,
+                battery,
+                rssi,
+                payload: HexString::from_bytes(&packet.data.to_vec()).as_string(),
+                frame: buffer_string.to_string(),
+            };
+            staleness::node_seen(state, config, &reading, now);
+            TEMPERATURE_GAUGE.with_label_values(&[&address, node]).set(temperature);
+            HUMIDITY_GAUGE.with_label_values(&[&address, node]).set(humidity);
+            set_derived_gauges(&[&address, node], temperature, humidity);
+            if let Some(battery) = battery {
+                BATTERY_GAUGE.with_label_values(&[&address, node]).set(battery);
+            }
+            if config.sinks.log {
+                info!("sensor data received; buffer={} address={} node={} temperature={} humidity={}", buffer_string, address, node, temperature, humidity);
+            }
+        }
+        Err(e) => {
+            SENSOR_ERRORS_COUNTER.with_label_values(&[&address, node, e.label()]).inc();
+            warn!("sensor data invalid; buffer={} address={} node={} error={}", buffer_string, address, node, e);
+        }
+    }
+}
+
+/// Tracks the sequence number of the node's packet, returning false for a duplicate, which is dropped.
+fn sequence_received(state: &State, labels: [&str; 2], sequence: u16) -> bool {
+    let (gap, counts) = state.sequences().received(labels[0], sequence);
+    let lost = PACKETS_LOST_COUNTER.with_label_values(&labels);
+    let duplicates = PACKETS_DUPLICATE_COUNTER.with_label_values(&labels);
+    PACKET_LOSS_GAUGE.with_label_values(&labels).set(counts.loss_ratio());
+    match gap {
+        Gap::Duplicate => {
+            duplicates.inc();
+            debug!("packet duplicate dropped; address={} node={} sequence={}", labels[0], labels[1], sequence);
+            return false;
+        }
+        Gap::Next { lost: 0 } | Gap::First => (),
+        Gap::Next { lost: missing } => {
+            lost.inc_by(missing as u64);
+            warn!("packets lost; address={} node={} sequence={} lost={}", labels[0], labels[1], sequence, missing);
+        }
+        Gap::Restart => info!("node restarted; address={} node={}", labels[0], labels[1]),
+    }
+    return true;
+}
+
+/// Sets the climate gauges derived from the temperature and humidity, which the HTU21 delivers as `f32`.
+fn set_derived_gauges(labels: &[&str], temperature: f64, humidity: f64) {
+    let (temperature, humidity) = (temperature as f32, humidity as f32);
+    DEW_POINT_GAUGE.with_label_values(labels).set(climate::dew_point(temperature, humidity) as f64);
+    ABSOLUTE_HUMIDITY_GAUGE.with_label_values(labels).set(climate::absolute_humidity(temperature, humidity) as f64);
+    VAPOR_PRESSURE_DEFICIT_GAUGE.with_label_values(labels)
+        .set(climate::vapor_pressure_deficit(temperature, humidity) as f64);
+    HEAT_INDEX_GAUGE.with_label_values(labels).set(climate::heat_index(temperature, humidity) as f64);
+}
+
+pub fn parse_sensor_values(data: &[u8], payload: &PayloadConfig) -> Result<SensorValues, Error> {
+    if payload.format == PayloadFormat::Tlv {
+        return parse_tlv_values(data);
+    }
+    // Raw payloads have a fixed length, which also keeps versioned payloads from being read as raw values.
+    if data.len() != payload.length() {
+        return Err(Error::InvalidPayloadLength(data.len()));
+    }
+    let temperature_data = raw_value(data, payload.temperature_offset, payload.crc)?;
+    let humidity_data = raw_value(data, payload.humidity_offset, payload.crc)?;
+    let temperature = htu21::parse_temperature(temperature_data)? as f64;
+    let humidity = htu21::parse_humidity(humidity_data)? as f64;
+    let battery = payload.battery_offset.map(|offset| data[offset] as f64);
+    return Ok(SensorValues { temperature, humidity, battery });
+}
+
+/// Returns the sequence number of the payload, which versioned payloads always carry, also along an error code.
+pub fn parse_sequence(data: &[u8], payload: &PayloadConfig) -> Option<u16> {
+    return match payload.format {
+        PayloadFormat::Raw => payload.sequence_offset
+            .filter(|offset| data.len() >= offset + 2)
+            .map(|offset| u16::from_be_bytes([data[offset], data[offset + 1]])),
+        PayloadFormat::Tlv => payload::decode(data).ok().map(|decoded| decoded.header.sequence),
+    };
+}
+
+/// Takes the values of a versioned payload.
+fn parse_tlv_values(data: &[u8]) -> Result<SensorValues, Error> {
+    let decoded = payload::decode(data)?;
+    let (mut temperature, mut humidity, mut battery, mut error_code) = (None, None, None, None);
+    for field in decoded.fields() {
+        match field {
+            payload::Field::Temperature(value) => temperature = Some(value as f64 / 100.0),
+            payload::Field::Humidity(value) => humidity = Some(value as f64 / 100.0),
+            payload::Field::Battery(value) => battery = Some(value as f64),
+            payload::Field::ErrorCode(code) => error_code = Some(code),
+            payload::Field::Timestamp(_) | payload::Field::Unknown { .. } => (),
+        }
+    }
+    return match (temperature, humidity, error_code) {
+        (Some(temperature), Some(humidity), _) =>
+            Ok(SensorValues { temperature, humidity, battery }),
+        (_, _, Some(code)) => Err(Error::NodeReported(code)),
+        _ => Err(Error::MissingValues),
+    };
+}
+
+/// Returns the two bytes of the raw value at the offset, checking the CRC byte following them if present.
+fn raw_value(data: &[u8], offset: usize, crc: bool) -> Result<&[u8], Error> {
+    let value = &data[offset..offset + 2];
+    if crc && !htu21::check(value, &data[offset + 2]) {
+        return Err(Error::InvalidCrc);
+    }
+    return Ok(value);
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use crate::config::TransportKind;
+    use crate::transport::Loopback;
+    use super::*;
+
+    fn raw() -> PayloadConfig {
+        return PayloadConfig { format: PayloadFormat::Raw, ..PayloadConfig::default() };
+    }
+
+    fn raw_config() -> Config {
+        return Config { payload: raw(), ..Config::default() };
+    }
+
+    #[test]
+    fn backoff() {
+        let mut backoff = Backoff::new();
+        let delays: Vec<u64> = (0..8).map(|_| backoff.next().as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 8, 16, 32, 60, 60]);
+        backoff.reset();
+        assert_eq!(backoff.next(), MIN_BACKOFF);
+    }
+
+    #[test]
+    fn stop() {
+        let config = Config {
+            serial: crate::config::SerialConfig { transport: TransportKind::Loopback, ..Default::default() },
+            ..Config::default()
+        };
+        let state = Arc::new(State::new());
+        let reader_state = state.clone();
+        let reader = thread::spawn(move || run(Arc::new(config), reader_state));
+        while !state.link().up {
+            thread::sleep(Duration::from_millis(10));
+        }
+        state.stop();
+        reader.join().unwrap();
+    }
+
+    #[test]
+    fn sensor_values() {
+        let values = parse_sensor_values(&[0x4e, 0x85, 0x68, 0x3a], &raw()).unwrap();
+        assert_eq!(values.temperature, 7.0436172f32 as f64);
+        assert_eq!(values.humidity, 44.88806f32 as f64);
+    }
+
+    #[test]
+    fn sensor_values_battery() {
+        let payload = PayloadConfig { battery_offset: Some(4), ..raw() };
+        let values = parse_sensor_values(&[0x4e, 0x85, 0x68, 0x3a, 0x57], &payload).unwrap();
+        assert_eq!(values.battery, Some(87.0));
+        let actual = parse_sensor_values(&[0x4e, 0x85, 0x68, 0x3a], &payload);
+        assert!(matches!(actual, Err(Error::InvalidPayloadLength(4))));
+    }
+
+    #[test]
+    fn sensor_values_short_payload() {
+        let actual = parse_sensor_values(&[0x4e, 0x85], &raw());
+        assert!(matches!(actual, Err(Error::InvalidPayloadLength(2))));
+    }
+
+    #[test]
+    fn sensor_values_swapped() {
+        let actual = parse_sensor_values(&[0x68, 0x3a, 0x4e, 0x85], &raw());
+        assert_eq!(actual.unwrap_err().label(), "not_temperature_sensor_value");
+    }
+
+    #[test]
+    fn sensor_values_tlv() {
+        let payload = PayloadConfig { format: PayloadFormat::Tlv, ..PayloadConfig::default() };
+        let data = [0x01, 0x01, 0x00, 0x2a, 0x00, 0x01, 0x02, 0x02, 0xc0, 0x02, 0x02, 0x11, 0x89, 0x03, 0x01, 0x57];
+        let values = parse_sensor_values(&data, &payload).unwrap();
+        assert_eq!((values.temperature, values.humidity), (7.04, 44.89));
+        assert_eq!(values.battery, Some(87.0));
+        assert_eq!(parse_sequence(&data, &payload), Some(42));
+        let actual = parse_sensor_values(&[0x01, 0x01, 0x00, 0x2b, 0x00, 0x05, 0x01, 0x02], &payload);
+        assert!(matches!(actual, Err(Error::NodeReported(0x02))));
+        let actual = parse_sensor_values(&data[..8], &payload);
+        assert_eq!(actual.unwrap_err().label(), "truncated_payload");
+        let actual = parse_sensor_values(&[0x4e, 0x85, 0x68, 0x3a], &payload);
+        assert_eq!(actual.unwrap_err().label(), "unsupported_payload_version");
+    }
+
+    #[test]
+    fn sensor_values_tlv_as_raw() {
+        let data = [0x01, 0x01, 0x00, 0x2a, 0x00, 0x01, 0x02, 0x02, 0xc0, 0x02, 0x02, 0x11, 0x89];
+        let actual = parse_sensor_values(&data, &raw());
+        assert!(matches!(actual, Err(Error::InvalidPayloadLength(13))));
+        let payload = PayloadConfig { battery_offset: Some(4), sequence_offset: Some(5), ..raw() };
+        let actual = parse_sensor_values(&data, &payload);
+        assert!(matches!(actual, Err(Error::InvalidPayloadLength(13))));
+    }
+
+    #[test]
+    fn sensor_values_crc() {
+        let payload = PayloadConfig { humidity_offset: 3, crc: true, ..raw() };
+        let values = parse_sensor_values(&[0x4e, 0x85, 0x6b, 0x68, 0x3a, 0x7c], &payload).unwrap();
+        assert_eq!(values.humidity, 44.88806f32 as f64);
+        let actual = parse_sensor_values(&[0x4e, 0x85, 0x6b, 0x68, 0x3b, 0x7c], &payload);
+        assert_eq!(actual.unwrap_err().label(), "invalid_crc");
+    }
+
+    #[test]
+    fn rssi_query() {
+        let (local, mut remote) = Loopback::pair();
+        let mut transport: Box<dyn Transport> = Box::new(local);
+        query_rssi(&mut transport).unwrap();
+        let mut query = [0x00u8; 8];
+        remote.read_exact(&mut query).unwrap();
+        assert_eq!(query, [0x7e, 0x00, 0x04, 0x08, 0x01, 0x44, 0x42, 0x70]);
+    }
+
+    #[test]
+    fn rssi_response() {
+        let response = [0x7e, 0x00, 0x06, 0x88, 0x01, 0x44, 0x42, 0x00, 0x28, 0xc8];
+        handle_frame(&response, &Config::default(), &State::new());
+        assert_eq!(COORDINATOR_RSSI_GAUGE.get(), -40.0);
+    }
+
+    #[tokio::test]
+    async fn command_round_trip() {
+        let state = Arc::new(State::new());
+        let sending = state.clone();
+        let delivery = tokio::spawn(async move {
+            return sending.transmitter().send("5678", &[0x01], Duration::from_secs(5)).await;
+        });
+        tokio::task::yield_now().await;
+        let (local, mut remote) = Loopback::pair();
+        let mut transport: Box<dyn Transport> = Box::new(local);
+        write_commands(&mut transport, &state).unwrap();
+        let mut tx_request = [0x00u8; 10];
+        remote.read_exact(&mut tx_request).unwrap();
+        assert_eq!(tx_request, [0x7e, 0x00, 0x06, 0x01, 0x02, 0x56, 0x78, 0x00, 0x01, 0x2d]);
+        handle_frame(&[0x7e, 0x00, 0x03, 0x89, 0x02, 0x00, 0x74], &Config::default(), &state);
+        assert_eq!(delivery.await.unwrap().unwrap().status, "success");
+    }
+
+    #[test]
+    fn rssi_of_packet() {
+        let frame = [0x7e, 0x00, 0x09, 0x81, 0x56, 0x78, 0x28, 0x00, 0x4e, 0x85, 0x68, 0x3a, 0x13];
+        handle_frame(&frame, &raw_config(), &State::new());
+        assert_eq!(RSSI_GAUGE.with_label_values(&["5678", "5678"]).get(), -40.0);
+        assert_eq!(RSSI_HISTOGRAM.with_label_values(&["5678", "5678"]).get_sample_count(), 1);
+    }
+
+    #[test]
+    fn derived_gauges() {
+        let frame = [0x7e, 0x00, 0x09, 0x81, 0x65, 0x43, 0x28, 0x00, 0x4e, 0x85, 0x68, 0x3a, 0x39];
+        handle_frame(&frame, &raw_config(), &State::new());
+        let temperature = TEMPERATURE_GAUGE.with_label_values(&["6543", "6543"]).get() as f32;
+        let humidity = HUMIDITY_GAUGE.with_label_values(&["6543", "6543"]).get() as f32;
+        let dew_point = DEW_POINT_GAUGE.with_label_values(&["6543", "6543"]).get();
+        assert_eq!(dew_point, climate::dew_point(temperature, humidity) as f64);
+        assert!(dew_point < temperature as f64);
+    }
+
+    #[test]
+    fn sensor_frame_counters() {
+        let node = NODE_PACKETS_COUNTER.with_label_values(&["4321", "4321"]);
+        let errors = SENSOR_ERRORS_COUNTER.with_label_values(&["4321", "4321", "invalid_payload_length"]);
+        let frame = [0x7e, 0x00, 0x07, 0x81, 0x43, 0x21, 0x28, 0x00, 0x4e, 0x85, 0x1f];
+        handle_frame(&frame, &raw_config(), &State::new());
+        assert_eq!(node.get(), 1);
+        assert_eq!(errors.get(), 1);
+        assert!(FRAMES_COUNTER.with_label_values(&["rx16"]).get() >= 1);
+    }
+
+    #[test]
+    fn sequence_numbers() {
+        let mut config = raw_config();
+        config.payload.sequence_offset = Some(4);
+        let state = State::new();
+        for sequence in [1u8, 1, 4] {
+            let mut frame = vec![0x7e, 0x00, 0x0b, 0x81, 0x34, 0x56, 0x28, 0x00, 0x4e, 0x85, 0x68, 0x3a, 0x00, sequence];
+            let sum = frame[3..].iter().fold(0u8, |sum, byte| sum.wrapping_add(*byte));
+            frame.push(0xff - sum);
+            handle_frame(&frame, &config, &state);
+        }
+        assert_eq!(state.readings("3456").unwrap().len(), 2);
+        assert_eq!(PACKETS_LOST_COUNTER.with_label_values(&["3456", "3456"]).get(), 2);
+        assert_eq!(PACKETS_DUPLICATE_COUNTER.with_label_values(&["3456", "3456"]).get(), 1);
+        assert_eq!(PACKET_LOSS_GAUGE.with_label_values(&["3456", "3456"]).get(), 0.5);
+    }
+}
@@ -0,0 +1,49 @@
+use serde::Serialize;
+
+/// Decoded sensor values of one receive packet.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Reading {
+    /// Lower case hex source address.
+    pub address: String,
+    pub node: String,
+    /// Unix time in seconds.
+    pub timestamp: f64,
+    pub temperature: f64,
+    pub humidity: f64,
+    /// Battery level in percent, if the payload carries one.
+    pub battery: Option<f64>,
+    /// Received signal strength in dBm.
+    pub rssi: f64,
+    /// Lower case hex payload of the receive packet.
+    pub payload: String,
+    /// Lower case hex API frame the reading was received in.
+    pub frame: String,
+}
+
+/// API frame with a valid checksum, as received from the XBee module.
+#[derive(Serialize, Clone, Debug, PartialEq)]
+pub struct Frame {
+    /// Unix time in seconds.
+    pub timestamp: f64,
+    pub api_identifier: &'static str,
+    /// Lower case hex frame.
+    pub frame: String,
+}
+
+#[cfg(test)]
+impl Reading {
+    /// Returns a reading of the node with fixed values.
+    pub fn example(address: &str, node: &str, time: std::time::SystemTime) -> Reading {
+        return Reading {
+            address: address.to_string(),
+            node: node.to_string(),
+            timestamp: crate::state::unix_seconds(time),
+            temperature: 21.5,
+            humidity: 40.0,
+            battery: None,
+            rssi: -40.0,
+            payload: "68dc2b4e8560".to_string(),
+            frame: "7e000b81abcd280068dc2b4e85603c".to_string(),
+        };
+    }
+}
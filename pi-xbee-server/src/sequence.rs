@@ -0,0 +1,124 @@
+use std::collections::HashMap;
+use std::sync::Mutex;
+
+/// How a sequence number follows the previous one of the node.
+///
+/// Nodes count their packets from 0 after a reset and wrap from 65535 to 1, so 0 marks a restart.
+#[derive(Debug, PartialEq)]
+pub enum Gap {
+    /// First packet of the node since the server started.
+    First,
+    /// Next packet of the node, after `lost` packets that never arrived.
+    Next { lost: u16 },
+    /// Same sequence number as the previous packet, e.g. resent after a lost acknowledgement.
+    Duplicate,
+    /// The node restarted, or its sequence number jumped back or too far ahead to count lost packets; packets lost
+    /// right before are not known.
+    Restart,
+}
+
+/// Largest forward distance counted as lost packets; a node restarting with its packet 0 lost jumps back, which
+/// would otherwise count as nearly 65535 lost packets.
+const MAX_DISTANCE: u16 = 32_767;
+
+pub fn gap(previous: Option<u16>, sequence: u16) -> Gap {
+    let previous = match previous {
+        Some(previous) => previous,
+        None => return Gap::First,
+    };
+    if sequence == previous {
+        return Gap::Duplicate;
+    }
+    if sequence == 0 {
+        return Gap::Restart;
+    }
+    let distance = if sequence > previous {
+        sequence - previous
+    } else {
+        u16::MAX - previous + sequence
+    };
+    if distance > MAX_DISTANCE {
+        return Gap::Restart;
+    }
+    return Gap::Next { lost: distance - 1 };
+}
+
+/// Packet counts of a node sending sequence numbers.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Counts {
+    pub last: u16,
+    /// Packets received once.
+    pub received: u64,
+    pub lost: u64,
+    pub duplicates: u64,
+}
+
+impl Counts {
+    /// Share of the packets sent since the first one received that were lost.
+    pub fn loss_ratio(&self) -> f64 {
+        let sent = self.received + self.lost;
+        return if sent == 0 { 0.0 } else { self.lost as f64 / sent as f64 };
+    }
+}
+
+/// Last sequence number and packet counts by node address.
+#[derive(Default)]
+pub struct Sequences {
+    nodes: Mutex<HashMap<String, Counts>>,
+}
+
+impl Sequences {
+    /// Records the sequence number of a packet of the node and returns how it followed the previous one.
+    pub fn received(&self, address: &str, sequence: u16) -> (Gap, Counts) {
+        let mut nodes = self.nodes.lock().unwrap();
+        let previous = nodes.get(address).map(|counts| counts.last);
+        let counts = nodes.entry(address.to_string()).or_default();
+        let gap = gap(previous, sequence);
+        match gap {
+            Gap::Duplicate => counts.duplicates += 1,
+            Gap::Next { lost } => {
+                counts.received += 1;
+                counts.lost += lost as u64;
+            }
+            Gap::First | Gap::Restart => counts.received += 1,
+        }
+        counts.last = sequence;
+        return (gap, counts.clone());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gaps() {
+        assert_eq!(gap(None, 7), Gap::First);
+        assert_eq!(gap(Some(7), 8), Gap::Next { lost: 0 });
+        assert_eq!(gap(Some(7), 10), Gap::Next { lost: 2 });
+        assert_eq!(gap(Some(7), 7), Gap::Duplicate);
+        assert_eq!(gap(Some(7), 0), Gap::Restart);
+        assert_eq!(gap(Some(0), 0), Gap::Duplicate);
+        assert_eq!(gap(Some(0), 1), Gap::Next { lost: 0 });
+        assert_eq!(gap(Some(u16::MAX), 1), Gap::Next { lost: 0 });
+        assert_eq!(gap(Some(u16::MAX - 1), 2), Gap::Next { lost: 2 });
+        assert_eq!(gap(Some(500), 1), Gap::Restart);
+        assert_eq!(gap(Some(7), 6), Gap::Restart);
+        assert_eq!(gap(Some(7), 7 + MAX_DISTANCE), Gap::Next { lost: MAX_DISTANCE - 1 });
+        assert_eq!(gap(Some(7), 8 + MAX_DISTANCE), Gap::Restart);
+        assert_eq!(gap(Some(u16::MAX - 10), 10), Gap::Next { lost: 19 });
+    }
+
+    #[test]
+    fn counts() {
+        let sequences = Sequences::default();
+        for sequence in [0, 1, 1, 4, 0] {
+            sequences.received("abcd", sequence);
+        }
+        let (gap, counts) = sequences.received("abcd", 1);
+        assert_eq!(gap, Gap::Next { lost: 0 });
+        assert_eq!(counts, Counts { last: 1, received: 5, lost: 2, duplicates: 1 });
+        assert_eq!(counts.loss_ratio(), 2.0 / 7.0);
+        assert_eq!(sequences.received("1234", 9).0, Gap::First);
+    }
+}
@@ -0,0 +1,20 @@
+use std::process;
+use log::warn;
+use tokio::signal::unix::{self, SignalKind};
+
+/// Waits for SIGTERM or SIGINT and returns its name.
+pub async fn signal() -> &'static str {
+    let mut terminate = unix::signal(SignalKind::terminate()).expect("SIGTERM handler installable");
+    let mut interrupt = unix::signal(SignalKind::interrupt()).expect("SIGINT handler installable");
+    return tokio::select! {
+        _ = terminate.recv() => "SIGTERM",
+        _ = interrupt.recv() => "SIGINT",
+    };
+}
+
+/// Exits immediately on a second signal, for when the graceful shutdown hangs.
+pub async fn force_on_signal() {
+    let signal = signal().await;
+    warn!("shutdown forced; signal={}", signal);
+    process::exit(130);
+}
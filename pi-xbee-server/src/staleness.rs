@@ -0,0 +1,107 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use log::{info, warn};
+use crate::config::{Config, StalePolicy};
+use crate::metrics::{ABSOLUTE_HUMIDITY_GAUGE, BATTERY_GAUGE, DEW_POINT_GAUGE, HEAT_INDEX_GAUGE, HUMIDITY_GAUGE,
+                     LAST_SEEN_GAUGE, NODE_STALE_GAUGE, RSSI_GAUGE, TEMPERATURE_GAUGE, VAPOR_PRESSURE_DEFICIT_GAUGE};
+use crate::reading::Reading;
+use crate::state::{self, State};
+
+/// Upper bound of the interval between staleness checks.
+const MAX_CHECK_INTERVAL: Duration = Duration::from_secs(10);
+
+/// Checks the nodes for staleness periodically.
+pub async fn run(config: Arc<Config>, state: Arc<State>) {
+    let timeout = Duration::from_secs(config.health.node_timeout);
+    let mut interval = tokio::time::interval(MAX_CHECK_INTERVAL.min(timeout));
+    loop {
+        interval.tick().await;
+        check(&state, &config, SystemTime::now());
+    }
+}
+
+/// Applies the stale policy to the nodes that became stale since the last check.
+pub fn check(state: &State, config: &Config, now: SystemTime) {
+    let last_seen = match now.checked_sub(Duration::from_secs(config.health.node_timeout)) {
+        Some(last_seen) => last_seen,
+        None => return,
+    };
+    for (address, node) in state.mark_stale(last_seen) {
+        let age = now.duration_since(node.last_seen).unwrap_or_default();
+        warn!("node stale; address={} node={} last_seen_age={}s policy={:?}",
+            address, node.name, age.as_secs(), config.health.stale);
+        let labels = [address.as_str(), node.name.as_str()];
+        match config.health.stale {
+            StalePolicy::Keep => (),
+            StalePolicy::Mark => NODE_STALE_GAUGE.with_label_values(&labels).set(1.0),
+            StalePolicy::Drop => {
+                let _ = TEMPERATURE_GAUGE.remove_label_values(&labels);
+                let _ = HUMIDITY_GAUGE.remove_label_values(&labels);
+                let _ = BATTERY_GAUGE.remove_label_values(&labels);
+                let _ = DEW_POINT_GAUGE.remove_label_values(&labels);
+                let _ = ABSOLUTE_HUMIDITY_GAUGE.remove_label_values(&labels);
+                let _ = VAPOR_PRESSURE_DEFICIT_GAUGE.remove_label_values(&labels);
+                let _ = HEAT_INDEX_GAUGE.remove_label_values(&labels);
+                let _ = RSSI_GAUGE.remove_label_values(&labels);
+            }
+        }
+    }
+}
+
+/// Records a reading of the node, reverting the stale policy if the node was stale.
+pub fn node_seen(state: &State, config: &Config, reading: &Reading, now: SystemTime) {
+    let (address, node) = (reading.address.as_str(), reading.node.as_str());
+    LAST_SEEN_GAUGE.with_label_values(&[address, node]).set(state::unix_seconds(now));
+    if let Some(previous) = state.node_seen(reading, now) {
+        if previous.stale {
+            info!("node recovered; address={} node={}", address, node);
+        }
+    }
+    if config.health.stale == StalePolicy::Mark {
+        NODE_STALE_GAUGE.with_label_values(&[address, node]).set(0.0);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn transition() {
+        let state = State::new();
+        let mut config = Config::default();
+        config.health.node_timeout = 60;
+        config.health.stale = StalePolicy::Mark;
+        let stale = NODE_STALE_GAUGE.with_label_values(&["abcd", "garage"]);
+
+        node_seen(&state, &config, &Reading::example("abcd", "garage", state.started), state.started);
+        assert_eq!(stale.get(), 0.0);
+        check(&state, &config, state.started + Duration::from_secs(60));
+        assert_eq!(stale.get(), 0.0);
+        check(&state, &config, state.started + Duration::from_secs(61));
+        assert_eq!(stale.get(), 1.0);
+        assert!(state.nodes()["abcd"].stale);
+        assert!(state.mark_stale(state.started + Duration::from_secs(62)).is_empty());
+
+        let now = state.started + Duration::from_secs(70);
+        node_seen(&state, &config, &Reading::example("abcd", "garage", now), now);
+        assert_eq!(stale.get(), 0.0);
+        assert!(!state.nodes()["abcd"].stale);
+    }
+
+    #[test]
+    fn drop() {
+        let state = State::new();
+        let mut config = Config::default();
+        config.health.node_timeout = 60;
+        config.health.stale = StalePolicy::Drop;
+        TEMPERATURE_GAUGE.with_label_values(&["dcba", "shed"]).set(20.0);
+        RSSI_GAUGE.with_label_values(&["dcba", "shed"]).set(-40.0);
+
+        node_seen(&state, &config, &Reading::example("dcba", "shed", state.started), state.started);
+        check(&state, &config, state.started + Duration::from_secs(61));
+        assert!(TEMPERATURE_GAUGE.remove_label_values(&["dcba", "shed"]).is_err());
+        assert!(RSSI_GAUGE.remove_label_values(&["dcba", "shed"]).is_err());
+        assert_eq!(LAST_SEEN_GAUGE.with_label_values(&["dcba", "shed"]).get(), state::unix_seconds(state.started));
+    }
+}
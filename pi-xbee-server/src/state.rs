@@ -0,0 +1,229 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::sync::Mutex;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+use tokio::sync::broadcast;
+use xbee::ModemStatus;
+use crate::command::Transmitter;
+use crate::metrics::LINK_UP_GAUGE;
+use crate::reading::{Frame, Reading};
+use crate::sequence::Sequences;
+
+/// Number of readings a slow subscriber may fall behind before it misses some.
+const SUBSCRIBER_CAPACITY: usize = 1024;
+
+pub fn unix_seconds(time: SystemTime) -> f64 {
+    return time.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs_f64();
+}
+
+/// Subscribes to the sender, or returns a receiver that is closed already if the sender is gone.
+fn subscribe<T: Clone>(sender: &Option<broadcast::Sender<T>>) -> broadcast::Receiver<T> {
+    return match sender {
+        Some(sender) => sender.subscribe(),
+        None => broadcast::channel(1).1,
+    };
+}
+
+/// Status of the transport to the XBee module.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Link {
+    pub up: bool,
+    /// Why the link went down, if it is down.
+    pub error: Option<String>,
+    pub since: SystemTime,
+}
+
+#[derive(Clone, Debug, PartialEq)]
+pub struct Node {
+    pub name: String,
+    pub last_seen: SystemTime,
+    /// Whether the node was found stale and has not sent a reading since.
+    pub stale: bool,
+    pub reading: Reading,
+}
+
+/// State shared between the reader and the HTTP server.
+pub struct State {
+    pub started: SystemTime,
+    link: Mutex<Link>,
+    last_frame: Mutex<Option<SystemTime>>,
+    modem_status: Mutex<Option<(ModemStatus, SystemTime)>>,
+    nodes: Mutex<BTreeMap<String, Node>>,
+    readings: Mutex<HashMap<String, VecDeque<Reading>>>,
+    /// Number of recent readings kept per node.
+    recent_readings: usize,
+    /// Publishes every reading to the sinks, `None` once closed.
+    sender: Mutex<Option<broadcast::Sender<Reading>>>,
+    /// Publishes every valid frame to the live stream, `None` once closed.
+    frames: Mutex<Option<broadcast::Sender<Frame>>>,
+    /// Whether the reader was asked to stop.
+    stopping: AtomicBool,
+    transmitter: Transmitter,
+    sequences: Sequences,
+}
+
+impl Default for State {
+    fn default() -> Self {
+        return State::new();
+    }
+}
+
+impl State {
+    pub fn new() -> State {
+        return State::with_recent_readings(100);
+    }
+
+    /// Keeps at most `recent_readings` readings per node.
+    pub fn with_recent_readings(recent_readings: usize) -> State {
+        let now = SystemTime::now();
+        return State {
+            started: now,
+            link: Mutex::new(Link {
+                up: false,
+                error: Some("not opened yet".to_string()),
+                since: now,
+            }),
+            last_frame: Mutex::new(None),
+            modem_status: Mutex::new(None),
+            nodes: Mutex::new(BTreeMap::new()),
+            readings: Mutex::new(HashMap::new()),
+            recent_readings,
+            sender: Mutex::new(Some(broadcast::channel(SUBSCRIBER_CAPACITY).0)),
+            frames: Mutex::new(Some(broadcast::channel(SUBSCRIBER_CAPACITY).0)),
+            stopping: AtomicBool::new(false),
+            transmitter: Transmitter::new(),
+            sequences: Sequences::default(),
+        };
+    }
+
+    pub fn link(&self) -> Link {
+        return self.link.lock().unwrap().clone();
+    }
+
+    pub fn link_up(&self) {
+        LINK_UP_GAUGE.set(1.0);
+        *self.link.lock().unwrap() = Link { up: true, error: None, since: SystemTime::now() };
+    }
+
+    pub fn link_down(&self, error: String) {
+        LINK_UP_GAUGE.set(0.0);
+        let mut link = self.link.lock().unwrap();
+        let since = if link.up { SystemTime::now() } else { link.since };
+        *link = Link { up: false, error: Some(error), since };
+    }
+
+    /// Time of the last frame with a valid checksum.
+    pub fn last_frame(&self) -> Option<SystemTime> {
+        return *self.last_frame.lock().unwrap();
+    }
+
+    pub fn frame_received(&self, time: SystemTime) {
+        *self.last_frame.lock().unwrap() = Some(time);
+    }
+
+    /// Last modem status reported by the coordinator and when it was received.
+    pub fn modem_status(&self) -> Option<(ModemStatus, SystemTime)> {
+        return *self.modem_status.lock().unwrap();
+    }
+
+    pub fn modem_status_received(&self, status: ModemStatus, time: SystemTime) {
+        *self.modem_status.lock().unwrap() = Some((status, time));
+    }
+
+    /// Nodes by address.
+    pub fn nodes(&self) -> BTreeMap<String, Node> {
+        return self.nodes.lock().unwrap().clone();
+    }
+
+    /// Records a reading of the node and returns the node as it was before, if it was known.
+    pub fn node_seen(&self, reading: &Reading, time: SystemTime) -> Option<Node> {
+        let mut readings = self.readings.lock().unwrap();
+        let recent = readings.entry(reading.address.clone()).or_default();
+        if recent.len() == self.recent_readings {
+            recent.pop_front();
+        }
+        recent.push_back(reading.clone());
+        if let Some(sender) = &*self.sender.lock().unwrap() {
+            let _ = sender.send(reading.clone());
+        }
+        let node = Node { name: reading.node.clone(), last_seen: time, stale: false, reading: reading.clone() };
+        return self.nodes.lock().unwrap().insert(reading.address.clone(), node);
+    }
+
+    /// Returns a receiver of all readings recorded from now on.
+    pub fn subscribe(&self) -> broadcast::Receiver<Reading> {
+        return subscribe(&self.sender.lock().unwrap());
+    }
+
+    pub fn publish_frame(&self, frame: Frame) {
+        if let Some(frames) = &*self.frames.lock().unwrap() {
+            let _ = frames.send(frame);
+        }
+    }
+
+    /// Returns a receiver of all valid frames received from now on.
+    pub fn subscribe_frames(&self) -> broadcast::Receiver<Frame> {
+        return subscribe(&self.frames.lock().unwrap());
+    }
+
+    /// Asks the reader to stop before the next frame.
+    pub fn stop(&self) {
+        self.stopping.store(true, Ordering::Relaxed);
+    }
+
+    pub fn stopping(&self) -> bool {
+        return self.stopping.load(Ordering::Relaxed);
+    }
+
+    /// Stops publishing readings and frames; subscribers receive what was published before and then `Closed`.
+    pub fn close(&self) {
+        self.sender.lock().unwrap().take();
+        self.frames.lock().unwrap().take();
+    }
+
+    /// Commands waiting to be written by the reader or for their TX Status.
+    pub fn transmitter(&self) -> &Transmitter {
+        return &self.transmitter;
+    }
+
+    /// Sequence numbers of the nodes whose payload carries one.
+    pub fn sequences(&self) -> &Sequences {
+        return &self.sequences;
+    }
+
+    /// Recent readings of the node, oldest first, or `None` if the node is unknown.
+    pub fn readings(&self, address: &str) -> Option<Vec<Reading>> {
+        return self.readings.lock().unwrap().get(address)
+            .map(|readings| readings.iter().cloned().collect());
+    }
+
+    /// Marks the nodes not seen since `last_seen` as stale and returns those that were not stale before.
+    pub fn mark_stale(&self, last_seen: SystemTime) -> Vec<(String, Node)> {
+        let mut nodes = self.nodes.lock().unwrap();
+        let mut marked = Vec::new();
+        for (address, node) in nodes.iter_mut() {
+            if !node.stale && node.last_seen < last_seen {
+                node.stale = true;
+                marked.push((address.clone(), node.clone()));
+            }
+        }
+        return marked;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use tokio::sync::broadcast::error::RecvError;
+    use super::*;
+
+    #[tokio::test]
+    async fn close() {
+        let state = State::new();
+        let mut receiver = state.subscribe();
+        state.node_seen(&Reading::example("abcd", "garage", state.started), state.started);
+        state.close();
+        assert_eq!(receiver.recv().await.unwrap().address, "abcd");
+        assert_eq!(receiver.recv().await, Err(RecvError::Closed));
+        assert_eq!(state.subscribe().recv().await, Err(RecvError::Closed));
+    }
+}
@@ -0,0 +1,205 @@
+use std::path::Path;
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant, SystemTime};
+use log::{info, warn};
+use rusqlite::{Connection, params};
+use tokio::sync::broadcast::{self, error::RecvError};
+use crate::config::Config;
+use crate::reading::Reading;
+use crate::state;
+
+/// Schema migrations, `PRAGMA user_version` is the number of migrations applied.
+const MIGRATIONS: [&str; 2] = [
+    "CREATE TABLE readings (
+        id INTEGER PRIMARY KEY,
+        address TEXT NOT NULL,
+        node TEXT NOT NULL,
+        timestamp REAL NOT NULL,
+        temperature REAL NOT NULL,
+        humidity REAL NOT NULL,
+        rssi REAL NOT NULL,
+        payload TEXT NOT NULL,
+        frame TEXT NOT NULL
+    );
+    CREATE INDEX readings_address_timestamp ON readings (address, timestamp);",
+    "ALTER TABLE readings ADD COLUMN battery REAL;",
+];
+
+/// Interval between deletions of the readings older than the retention.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(3_600);
+
+/// Readings stored in a SQLite database.
+pub struct Storage {
+    connection: Connection,
+}
+
+/// Time range of a query, in unix seconds.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Range {
+    pub from: Option<f64>,
+    pub to: Option<f64>,
+}
+
+impl Range {
+    pub fn contains(&self, timestamp: f64) -> bool {
+        return self.from.is_none_or(|from| timestamp >= from) && self.to.is_none_or(|to| timestamp <= to);
+    }
+}
+
+impl Storage {
+    /// Opens or creates the database and applies the pending migrations.
+    pub fn open(path: &Path) -> rusqlite::Result<Storage> {
+        return Storage::migrate(Connection::open(path)?);
+    }
+
+    #[cfg(test)]
+    pub fn open_in_memory() -> rusqlite::Result<Storage> {
+        return Storage::migrate(Connection::open_in_memory()?);
+    }
+
+    fn migrate(mut connection: Connection) -> rusqlite::Result<Storage> {
+        let version: usize = connection.query_row("PRAGMA user_version", [], |row| row.get(0))?;
+        for (i, migration) in MIGRATIONS.iter().enumerate().skip(version) {
+            let transaction = connection.transaction()?;
+            transaction.execute_batch(migration)?;
+            transaction.pragma_update(None, "user_version", i + 1)?;
+            transaction.commit()?;
+            info!("sqlite migration applied; version={}", i + 1);
+        }
+        return Ok(Storage { connection });
+    }
+
+    pub fn insert(&self, reading: &Reading) -> rusqlite::Result<()> {
+        self.connection.prepare_cached(
+            "INSERT INTO readings (address, node, timestamp, temperature, humidity, rssi, payload, frame, battery)
+             VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)")?
+            .execute(params![reading.address, reading.node, reading.timestamp, reading.temperature,
+                reading.humidity, reading.rssi, reading.payload, reading.frame, reading.battery])?;
+        return Ok(());
+    }
+
+    /// Deletes the readings taken before `timestamp` and returns how many were deleted.
+    pub fn prune(&self, timestamp: f64) -> rusqlite::Result<usize> {
+        return self.connection.execute("DELETE FROM readings WHERE timestamp < ?1", [timestamp]);
+    }
+
+    /// Returns the most recent readings of the node within the range, oldest first.
+    pub fn readings(&self, address: &str, range: Range, limit: Option<usize>) -> rusqlite::Result<Vec<Reading>> {
+        let mut statement = self.connection.prepare_cached(
+            "SELECT address, node, timestamp, temperature, humidity, rssi, payload, frame, battery FROM readings
+             WHERE address = ?1 AND timestamp >= ?2 AND timestamp <= ?3
+             ORDER BY timestamp DESC LIMIT ?4")?;
+        let limit = limit.map_or(-1, |limit| limit as i64);
+        let mut readings = statement
+            .query_map(params![address, range.from.unwrap_or(f64::MIN), range.to.unwrap_or(f64::MAX), limit], reading)?
+            .collect::<rusqlite::Result<Vec<Reading>>>()?;
+        readings.reverse();
+        return Ok(readings);
+    }
+
+    /// Whether any reading of the node is stored.
+    pub fn contains(&self, address: &str) -> rusqlite::Result<bool> {
+        return self.connection.prepare_cached("SELECT EXISTS (SELECT 1 FROM readings WHERE address = ?1)")?
+            .query_row([address], |row| row.get(0));
+    }
+
+    /// Calls `f` with every reading taken at or after `from`, oldest first.
+    pub fn for_each_since(&self, from: f64, mut f: impl FnMut(Reading)) -> rusqlite::Result<()> {
+        let mut statement = self.connection.prepare(
+            "SELECT address, node, timestamp, temperature, humidity, rssi, payload, frame, battery FROM readings
+             WHERE timestamp >= ?1 ORDER BY timestamp")?;
+        let mut rows = statement.query([from])?;
+        while let Some(row) = rows.next()? {
+            f(reading(row)?);
+        }
+        return Ok(());
+    }
+}
+
+fn reading(row: &rusqlite::Row) -> rusqlite::Result<Reading> {
+    return Ok(Reading {
+        address: row.get(0)?,
+        node: row.get(1)?,
+        timestamp: row.get(2)?,
+        temperature: row.get(3)?,
+        humidity: row.get(4)?,
+        battery: row.get(8)?,
+        rssi: row.get(5)?,
+        payload: row.get(6)?,
+        frame: row.get(7)?,
+    });
+}
+
+/// Stores the received readings, deleting those older than the retention.
+pub fn run(config: Arc<Config>, mut receiver: broadcast::Receiver<Reading>, storage: Arc<Mutex<Storage>>) {
+    let retention = Duration::from_secs(config.sqlite.retention_days * 86_400);
+    let mut last_prune: Option<Instant> = None;
+    loop {
+        let reading = match receiver.blocking_recv() {
+            Ok(reading) => reading,
+            Err(RecvError::Lagged(missed)) => {
+                warn!("sqlite sink lagging; missed={}", missed);
+                continue;
+            }
+            Err(RecvError::Closed) => {
+                info!("sqlite sink stopped;");
+                return;
+            }
+        };
+        let storage = storage.lock().unwrap();
+        if let Err(e) = storage.insert(&reading) {
+            warn!("sqlite insert failed; address={} error={}", reading.address, e);
+        }
+        if retention.is_zero() || last_prune.is_some_and(|time| time.elapsed() < PRUNE_INTERVAL) {
+            continue;
+        }
+        last_prune = Some(Instant::now());
+        let before = SystemTime::now().checked_sub(retention).map_or(0.0, state::unix_seconds);
+        match storage.prune(before) {
+            Ok(deleted) => info!("sqlite readings pruned; deleted={} retention_days={}", deleted, config.sqlite.retention_days),
+            Err(e) => warn!("sqlite prune failed; error={}", e),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reading(address: &str, timestamp: f64) -> Reading {
+        return Reading { timestamp, ..Reading::example(address, "garage", SystemTime::now()) };
+    }
+
+    #[test]
+    fn migrations() {
+        let path = std::env::temp_dir().join(format!("pi-xbee-server-{}.db", std::process::id()));
+        let storage = Storage::open(&path).unwrap();
+        storage.insert(&reading("abcd", 1.0)).unwrap();
+        drop(storage);
+        let storage = Storage::open(&path).unwrap();
+        let version: usize = storage.connection.query_row("PRAGMA user_version", [], |row| row.get(0)).unwrap();
+        assert_eq!(version, MIGRATIONS.len());
+        assert_eq!(storage.readings("abcd", Range::default(), None).unwrap().len(), 1);
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn readings() {
+        let storage = Storage::open_in_memory().unwrap();
+        for timestamp in 1..=5 {
+            storage.insert(&reading("abcd", timestamp as f64)).unwrap();
+        }
+        storage.insert(&reading("1234", 3.0)).unwrap();
+
+        let range = Range { from: Some(2.0), to: Some(4.0) };
+        let timestamps = |readings: Vec<Reading>| readings.iter().map(|r| r.timestamp).collect::<Vec<f64>>();
+        assert_eq!(timestamps(storage.readings("abcd", range, None).unwrap()), [2.0, 3.0, 4.0]);
+        assert_eq!(timestamps(storage.readings("abcd", range, Some(2)).unwrap()), [3.0, 4.0]);
+        assert_eq!(storage.readings("abcd", range, None).unwrap()[0], reading("abcd", 2.0));
+        assert!(storage.contains("1234").unwrap());
+        assert!(!storage.contains("5678").unwrap());
+
+        assert_eq!(storage.prune(3.0).unwrap(), 2);
+        assert_eq!(timestamps(storage.readings("abcd", Range::default(), None).unwrap()), [3.0, 4.0, 5.0]);
+    }
+}
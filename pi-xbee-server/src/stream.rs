@@ -0,0 +1,90 @@
+use std::convert::Infallible;
+use std::sync::Arc;
+use futures_util::stream::{self, Stream};
+use log::debug;
+use tokio::sync::broadcast::{self, error::RecvError};
+use warp::{Filter, Rejection, Reply};
+use warp::sse::Event;
+use crate::auth::{self, Auth};
+use crate::reading::{Frame, Reading};
+use crate::state::State;
+
+/// Receivers of one client of the live stream.
+pub struct Subscription {
+    readings: broadcast::Receiver<Reading>,
+    frames: broadcast::Receiver<Frame>,
+}
+
+impl Subscription {
+    pub fn new(state: &State) -> Subscription {
+        return Subscription { readings: state.subscribe(), frames: state.subscribe_frames() };
+    }
+
+    /// Waits for the next reading or frame, or `None` once the state is gone.
+    ///
+    /// A client that falls behind the broadcast capacity skips the oldest events and is sent a
+    /// `lagged` event with the number skipped, so a slow client never holds back the others.
+    pub async fn next(&mut self) -> Option<Event> {
+        let (name, event) = tokio::select! {
+            reading = self.readings.recv() => ("reading", reading.map(|reading| Event::default().json_data(reading))),
+            frame = self.frames.recv() => ("frame", frame.map(|frame| Event::default().json_data(frame))),
+        };
+        return match event {
+            Ok(event) => Some(event.expect("event serializable").event(name)),
+            Err(RecvError::Lagged(missed)) => {
+                debug!("stream client lagging; kind={} missed={}", name, missed);
+                Some(Event::default().event("lagged").data(format!("{{\"{}\":{}}}", name, missed)))
+            }
+            Err(RecvError::Closed) => None,
+        };
+    }
+
+    fn into_stream(self) -> impl Stream<Item=Result<Event, Infallible>> {
+        return stream::unfold(self, |mut subscription| async move {
+            let event = subscription.next().await?;
+            return Some((Ok(event), subscription));
+        });
+    }
+}
+
+/// `GET /api/stream`: server-sent `reading`, `frame` and `lagged` events.
+pub fn route(state: Arc<State>, auth: Arc<Auth>) -> impl Filter<Extract=(impl Reply,), Error=Rejection> + Clone {
+    return warp::path!("api" / "stream")
+        .and(auth::read(auth))
+        .map(move || {
+            let events = Subscription::new(&state).into_stream();
+            return warp::sse::reply(warp::sse::keep_alive().stream(events));
+        });
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::SystemTime;
+    use super::*;
+
+    #[tokio::test]
+    async fn events() {
+        let state = State::new();
+        let mut subscription = Subscription::new(&state);
+        state.publish_frame(Frame { timestamp: 1.0, api_identifier: "rx16", frame: "7e".to_string() });
+        let event = subscription.next().await.unwrap();
+        assert_eq!(event.to_string(), "event:frame\ndata:{\"timestamp\":1.0,\"api_identifier\":\"rx16\",\"frame\":\"7e\"}\n\n");
+
+        state.node_seen(&Reading::example("abcd", "garage", SystemTime::UNIX_EPOCH), SystemTime::UNIX_EPOCH);
+        let event = subscription.next().await.unwrap().to_string();
+        assert!(event.starts_with("event:reading\ndata:{\"address\":\"abcd\""), "{}", event);
+    }
+
+    #[tokio::test]
+    async fn lagged() {
+        let state = State::with_recent_readings(1);
+        let mut subscription = Subscription::new(&state);
+        for i in 0..1_100 {
+            state.publish_frame(Frame { timestamp: i as f64, api_identifier: "rx16", frame: "7e".to_string() });
+        }
+        let event = subscription.next().await.unwrap();
+        assert_eq!(event.to_string(), "event:lagged\ndata:{\"frame\":76}\n\n");
+        let event = subscription.next().await.unwrap().to_string();
+        assert!(event.contains("\"timestamp\":76.0"), "{}", event);
+    }
+}
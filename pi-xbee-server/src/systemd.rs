@@ -0,0 +1,38 @@
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+use log::{debug, info, warn};
+use sd_notify::NotifyState;
+use crate::config::Config;
+use crate::health::{self, Probe};
+use crate::state::State;
+
+/// Tells systemd about the service state; does nothing unless started by a `Type=notify` unit.
+pub fn notify(state: &[NotifyState]) {
+    if let Err(e) = sd_notify::notify(false, state) {
+        warn!("systemd notify failed; error={}", e);
+    }
+}
+
+/// Returns half the watchdog timeout of the unit, the interval to ping at, if its watchdog is enabled.
+pub fn watchdog_interval() -> Option<Duration> {
+    let mut usec = 0;
+    if !sd_notify::watchdog_enabled(false, &mut usec) {
+        return None;
+    }
+    return Some(Duration::from_micros(usec) / 2);
+}
+
+/// Pings the watchdog while the liveness probe passes, so systemd restarts the service once it fails.
+pub async fn watchdog(interval: Duration, config: Arc<Config>, state: Arc<State>) {
+    info!("systemd watchdog enabled; interval={:?}", interval);
+    let mut ticks = tokio::time::interval(interval);
+    loop {
+        ticks.tick().await;
+        let report = health::report(&state, &config.health, Probe::Liveness, SystemTime::now());
+        if report.ok {
+            notify(&[NotifyState::Watchdog]);
+        } else {
+            debug!("systemd watchdog withheld; failures={:?}", report.failures);
+        }
+    }
+}
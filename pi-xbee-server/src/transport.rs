@@ -0,0 +1,226 @@
+use std::collections::VecDeque;
+use std::io::{self, Read, Write};
+use std::net::TcpStream;
+use std::sync::{Arc, Condvar, Mutex};
+use std::time::Duration;
+use crate::config::{Parity, SerialConfig, TransportKind};
+
+/// How long a read waits for data before it fails with `TimedOut` or `WouldBlock`.
+pub const READ_TIMEOUT: Duration = Duration::from_secs(1);
+
+/// Byte stream to the XBee module.
+///
+/// Reads wait at most [`READ_TIMEOUT`] and fail with `TimedOut` or `WouldBlock` if no data arrived.
+pub trait Transport: Read + Write + Send {}
+
+impl<T: Read + Write + Send> Transport for T {}
+
+pub fn open(config: &SerialConfig) -> io::Result<Box<dyn Transport>> {
+    return match config.transport {
+        TransportKind::Rppal => open_rppal(config),
+        TransportKind::Tty => open_tty(config),
+        TransportKind::Tcp => open_tcp(config),
+        TransportKind::Loopback => Ok(Box::new(Loopback::new())),
+    };
+}
+
+#[cfg(feature = "rppal")]
+fn open_rppal(config: &SerialConfig) -> io::Result<Box<dyn Transport>> {
+    use rppal::uart::Uart;
+    let parity = match config.parity {
+        Parity::None => rppal::uart::Parity::None,
+        Parity::Even => rppal::uart::Parity::Even,
+        Parity::Odd => rppal::uart::Parity::Odd,
+    };
+    let mut uart = Uart::with_path(&config.device, config.baud, parity,
+                                   config.data_bits, config.stop_bits)
+        .map_err(rppal_error)?;
+    uart.set_read_mode(0, READ_TIMEOUT).map_err(rppal_error)?;
+    uart.set_write_mode(true).map_err(rppal_error)?;
+    return Ok(Box::new(RppalUart(uart)));
+}
+
+#[cfg(not(feature = "rppal"))]
+fn open_rppal(_config: &SerialConfig) -> io::Result<Box<dyn Transport>> {
+    return Err(io::Error::new(io::ErrorKind::Unsupported, "built without the rppal feature"));
+}
+
+#[cfg(feature = "rppal")]
+fn rppal_error(e: rppal::uart::Error) -> io::Error {
+    return match e {
+        rppal::uart::Error::Io(e) => e,
+        e => io::Error::other(e),
+    };
+}
+
+/// `rppal` UART adapted to `std::io`; a read that times out without data fails with `TimedOut`.
+#[cfg(feature = "rppal")]
+struct RppalUart(rppal::uart::Uart);
+
+#[cfg(feature = "rppal")]
+impl Read for RppalUart {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        return match self.0.read(buffer).map_err(rppal_error)? {
+            0 if !buffer.is_empty() => Err(io::ErrorKind::TimedOut.into()),
+            bytes_read => Ok(bytes_read),
+        };
+    }
+}
+
+#[cfg(feature = "rppal")]
+impl Write for RppalUart {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        return self.0.write(buffer).map_err(rppal_error);
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return self.0.drain().map_err(rppal_error);
+    }
+}
+
+fn open_tty(config: &SerialConfig) -> io::Result<Box<dyn Transport>> {
+    let parity = match config.parity {
+        Parity::None => serialport::Parity::None,
+        Parity::Even => serialport::Parity::Even,
+        Parity::Odd => serialport::Parity::Odd,
+    };
+    let data_bits = match config.data_bits {
+        5 => serialport::DataBits::Five,
+        6 => serialport::DataBits::Six,
+        7 => serialport::DataBits::Seven,
+        _ => serialport::DataBits::Eight,
+    };
+    let stop_bits = match config.stop_bits {
+        2 => serialport::StopBits::Two,
+        _ => serialport::StopBits::One,
+    };
+    let port = serialport::new(config.device.as_str(), config.baud)
+        .parity(parity)
+        .data_bits(data_bits)
+        .stop_bits(stop_bits)
+        .timeout(READ_TIMEOUT)
+        .open()?;
+    return Ok(Box::new(port));
+}
+
+fn open_tcp(config: &SerialConfig) -> io::Result<Box<dyn Transport>> {
+    let stream = TcpStream::connect(config.address.as_str())?;
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    stream.set_nodelay(true)?;
+    return Ok(Box::new(stream));
+}
+
+#[derive(Default)]
+struct Pipe {
+    buffer: Mutex<VecDeque<u8>>,
+    available: Condvar,
+}
+
+/// In-memory transport; bytes written to one end are read from the other.
+pub struct Loopback {
+    rx: Arc<Pipe>,
+    tx: Arc<Pipe>,
+}
+
+impl Loopback {
+    /// Returns a single end that reads back what was written to it.
+    pub fn new() -> Loopback {
+        let pipe = Arc::new(Pipe::default());
+        return Loopback { rx: pipe.clone(), tx: pipe };
+    }
+
+    /// Returns two connected ends, like a null modem cable.
+    #[cfg(test)]
+    pub fn pair() -> (Loopback, Loopback) {
+        let a = Arc::new(Pipe::default());
+        let b = Arc::new(Pipe::default());
+        return (Loopback { rx: a.clone(), tx: b.clone() }, Loopback { rx: b, tx: a });
+    }
+}
+
+impl Default for Loopback {
+    fn default() -> Self {
+        return Loopback::new();
+    }
+}
+
+impl Read for Loopback {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let pending = self.rx.buffer.lock().unwrap();
+        let (mut pending, _) = self.rx.available
+            .wait_timeout_while(pending, READ_TIMEOUT, |pending| pending.is_empty())
+            .unwrap();
+        if pending.is_empty() && !buffer.is_empty() {
+            return Err(io::ErrorKind::TimedOut.into());
+        }
+        let length = buffer.len().min(pending.len());
+        for (i, byte) in pending.drain(..length).enumerate() {
+            buffer[i] = byte;
+        }
+        return Ok(length);
+    }
+}
+
+impl Write for Loopback {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        self.tx.buffer.lock().unwrap().extend(buffer);
+        self.tx.available.notify_all();
+        return Ok(buffer.len());
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn loopback_echo() {
+        let mut loopback = Loopback::new();
+        loopback.write_all(&[0x7e, 0x00]).unwrap();
+        let mut buffer = [0x00u8; 4];
+        assert_eq!(loopback.read(&mut buffer).unwrap(), 2);
+        assert_eq!(buffer[..2], [0x7e, 0x00]);
+    }
+
+    #[test]
+    fn loopback_pair() {
+        let (mut a, mut b) = Loopback::pair();
+        a.write_all(&[0x01, 0x02, 0x03]).unwrap();
+        b.write_all(&[0x04]).unwrap();
+        let mut buffer = [0x00u8; 2];
+        b.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer, [0x01, 0x02]);
+        b.read_exact(&mut buffer[..1]).unwrap();
+        assert_eq!(buffer[0], 0x03);
+        a.read_exact(&mut buffer[..1]).unwrap();
+        assert_eq!(buffer[0], 0x04);
+    }
+
+    #[test]
+    fn loopback_timeout() {
+        let mut loopback = Loopback::new();
+        let mut buffer = [0x00u8; 1];
+        let actual = loopback.read(&mut buffer).unwrap_err();
+        assert_eq!(actual.kind(), io::ErrorKind::TimedOut);
+    }
+
+    #[test]
+    fn tcp() {
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let config = SerialConfig {
+            transport: TransportKind::Tcp,
+            address: listener.local_addr().unwrap().to_string(),
+            ..SerialConfig::default()
+        };
+        let mut transport = open(&config).unwrap();
+        let (mut peer, _) = listener.accept().unwrap();
+        peer.write_all(&[0x7e]).unwrap();
+        let mut buffer = [0x00u8; 1];
+        transport.read_exact(&mut buffer).unwrap();
+        assert_eq!(buffer[0], 0x7e);
+    }
+}
@@ -1,14 +1,50 @@
 #![no_std]
 
+#[derive(PartialEq, Debug, Clone, Copy)]
 pub enum ApiIdentifier {
     /** 64-bit Transmit Request */
-    TxReq
+    TxReq,
+    /** 16-bit Transmit Request */
+    Tx16Req,
+    /** Transmit Status */
+    TxStatus,
+    /** 64-bit Receive Packet */
+    Rx64,
+    /** 16-bit Receive Packet */
+    Rx16,
+    /** AT Command */
+    AtCommand,
+    /** AT Command Response */
+    AtCommandResponse,
+    /** Modem Status */
+    ModemStatus,
 }
 
 impl ApiIdentifier {
     fn value(&self) -> u8 {
         match self {
-            ApiIdentifier::TxReq => 0x00
+            ApiIdentifier::TxReq => 0x00,
+            ApiIdentifier::Tx16Req => 0x01,
+            ApiIdentifier::AtCommand => 0x08,
+            ApiIdentifier::Rx64 => 0x80,
+            ApiIdentifier::Rx16 => 0x81,
+            ApiIdentifier::AtCommandResponse => 0x88,
+            ApiIdentifier::TxStatus => 0x89,
+            ApiIdentifier::ModemStatus => 0x8a,
+        }
+    }
+
+    pub fn from_value(value: u8) -> Option<ApiIdentifier> {
+        match value {
+            0x00 => Some(ApiIdentifier::TxReq),
+            0x01 => Some(ApiIdentifier::Tx16Req),
+            0x08 => Some(ApiIdentifier::AtCommand),
+            0x80 => Some(ApiIdentifier::Rx64),
+            0x81 => Some(ApiIdentifier::Rx16),
+            0x88 => Some(ApiIdentifier::AtCommandResponse),
+            0x89 => Some(ApiIdentifier::TxStatus),
+            0x8a => Some(ApiIdentifier::ModemStatus),
+            _ => None,
         }
     }
 }
@@ -99,6 +135,298 @@ impl Iterator for PacketIterator<'_> {
     }
 }
 
+pub const START_DELIMITER: u8 = 0x7e;
+pub const HEADER_LENGTH: usize = 3;
+
+#[derive(PartialEq, Debug)]
+pub enum Error {
+    NoStartDelimiter,
+    IncompleteFrame,
+    InvalidChecksum,
+    UnsupportedApiIdentifier(u8),
+    /** AT command parameter longer than [`MAX_AT_PARAMETER`]. */
+    ParameterTooLong(usize),
+    /** Transmit Request data longer than [`MAX_TX_DATA`]. */
+    DataTooLong(usize),
+}
+
+/** Returns the length of the complete frame (header, frame data and checksum) announced by the header. */
+pub fn frame_length(header: &[u8]) -> Result<usize, Error> {
+    if header.len() < HEADER_LENGTH {
+        return Err(Error::IncompleteFrame);
+    }
+    if header[0] != START_DELIMITER {
+        return Err(Error::NoStartDelimiter);
+    }
+    let length = u16::from_be_bytes([header[1], header[2]]) as usize;
+    return Ok(HEADER_LENGTH + length + 1);
+}
+
+/** Returns the frame data (API identifier and payload) after checking length and checksum. */
+fn frame_data(frame: &[u8]) -> Result<&[u8], Error> {
+    let length = frame_length(frame)?;
+    if frame.len() < length || length < HEADER_LENGTH + 2 {
+        return Err(Error::IncompleteFrame);
+    }
+    let frame_data = &frame[HEADER_LENGTH..length - 1];
+    let sum = frame_data.iter().fold(0u8, |acc, x| acc.wrapping_add(*x));
+    if 0xFF - sum != frame[length - 1] {
+        return Err(Error::InvalidChecksum);
+    }
+    return Ok(frame_data);
+}
+
+/** Returns the API identifier of a complete frame with a valid checksum. */
+pub fn api_identifier(frame: &[u8]) -> Result<ApiIdentifier, Error> {
+    let api_identifier = frame_data(frame)?[0];
+    return ApiIdentifier::from_value(api_identifier)
+        .ok_or(Error::UnsupportedApiIdentifier(api_identifier));
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum Address {
+    /** 16-bit network address */
+    Short([u8; 2]),
+    /** 64-bit serial number */
+    Long([u8; 8]),
+}
+
+impl Address {
+    pub fn as_slice(&self) -> &[u8] {
+        match self {
+            Address::Short(address) => address,
+            Address::Long(address) => address,
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct RxPacket<'a> {
+    pub source: Address,
+    /** received signal strength as -dBm */
+    pub rssi: u8,
+    pub options: u8,
+    pub data: &'a [u8],
+}
+
+/** Parses a 64-bit (0x80) or 16-bit (0x81) Receive Packet frame. */
+pub fn parse_rx_packet(frame: &[u8]) -> Result<RxPacket<'_>, Error> {
+    let frame_data = frame_data(frame)?;
+    let (source, rest) = match frame_data.first() {
+        Some(0x80) if frame_data.len() >= 11 => {
+            let mut address = [0x00u8; 8];
+            address.copy_from_slice(&frame_data[1..9]);
+            (Address::Long(address), &frame_data[9..])
+        }
+        Some(0x81) if frame_data.len() >= 5 => {
+            (Address::Short([frame_data[1], frame_data[2]]), &frame_data[3..])
+        }
+        Some(0x80) | Some(0x81) => return Err(Error::IncompleteFrame),
+        Some(api_identifier) => return Err(Error::UnsupportedApiIdentifier(*api_identifier)),
+        None => return Err(Error::IncompleteFrame),
+    };
+    return Ok(RxPacket {
+        source,
+        rssi: rest[0],
+        options: rest[1],
+        data: &rest[2..],
+    });
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum ModemStatus {
+    HardwareReset,
+    WatchdogTimerReset,
+    Associated,
+    Disassociated,
+    SynchronizationLost,
+    CoordinatorRealignment,
+    CoordinatorStarted,
+    Other(u8),
+}
+
+impl ModemStatus {
+    pub fn from_value(value: u8) -> ModemStatus {
+        match value {
+            0x00 => ModemStatus::HardwareReset,
+            0x01 => ModemStatus::WatchdogTimerReset,
+            0x02 => ModemStatus::Associated,
+            0x03 => ModemStatus::Disassociated,
+            0x04 => ModemStatus::SynchronizationLost,
+            0x05 => ModemStatus::CoordinatorRealignment,
+            0x06 => ModemStatus::CoordinatorStarted,
+            value => ModemStatus::Other(value),
+        }
+    }
+}
+
+/** Parses a Modem Status (0x8A) frame. */
+pub fn parse_modem_status(frame: &[u8]) -> Result<ModemStatus, Error> {
+    let frame_data = frame_data(frame)?;
+    if frame_data[0] != ApiIdentifier::ModemStatus.value() {
+        return Err(Error::UnsupportedApiIdentifier(frame_data[0]));
+    }
+    if frame_data.len() < 2 {
+        return Err(Error::IncompleteFrame);
+    }
+    return Ok(ModemStatus::from_value(frame_data[1]));
+}
+
+/** Maximum parameter of an AT Command. */
+pub const MAX_AT_PARAMETER: usize = 8;
+
+pub struct AtCommand {
+    bytes: [u8; HEADER_LENGTH + 4 + MAX_AT_PARAMETER + 1],
+    length: usize,
+}
+
+impl AtCommand {
+    /** Builds a local AT Command frame; the parameter is at most [`MAX_AT_PARAMETER`] bytes. */
+    pub fn new(frame_id: u8, command: [u8; 2], parameter: &[u8]) -> Result<AtCommand, Error> {
+        if parameter.len() > MAX_AT_PARAMETER {
+            return Err(Error::ParameterTooLong(parameter.len()));
+        }
+        let mut at_command = AtCommand {
+            bytes: [0x00; HEADER_LENGTH + 4 + MAX_AT_PARAMETER + 1],
+            length: HEADER_LENGTH + 4 + parameter.len() + 1,
+        };
+        let length = 4 + parameter.len();
+        at_command.bytes[0] = START_DELIMITER;
+        at_command.bytes[1] = (length >> 8) as u8;
+        at_command.bytes[2] = (length & 0x00ff) as u8;
+        at_command.bytes[3] = ApiIdentifier::AtCommand.value();
+        at_command.bytes[4] = frame_id;
+        at_command.bytes[5] = command[0];
+        at_command.bytes[6] = command[1];
+        parameter.iter().enumerate().for_each(|(i, e)| at_command.bytes[7 + i] = *e);
+        let sum = at_command.bytes[HEADER_LENGTH..at_command.length - 1].iter()
+            .fold(0u8, |acc, x| acc.wrapping_add(*x));
+        at_command.bytes[at_command.length - 1] = 0xFF - sum;
+        return Ok(at_command);
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        return &self.bytes[..self.length];
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct AtCommandResponse<'a> {
+    pub frame_id: u8,
+    pub command: [u8; 2],
+    /** 0 = OK, 1 = ERROR, 2 = Invalid Command, 3 = Invalid Parameter */
+    pub status: u8,
+    pub data: &'a [u8],
+}
+
+/** Parses an AT Command Response (0x88) frame. */
+pub fn parse_at_command_response(frame: &[u8]) -> Result<AtCommandResponse<'_>, Error> {
+    let frame_data = frame_data(frame)?;
+    if frame_data[0] != ApiIdentifier::AtCommandResponse.value() {
+        return Err(Error::UnsupportedApiIdentifier(frame_data[0]));
+    }
+    if frame_data.len() < 5 {
+        return Err(Error::IncompleteFrame);
+    }
+    return Ok(AtCommandResponse {
+        frame_id: frame_data[1],
+        command: [frame_data[2], frame_data[3]],
+        status: frame_data[4],
+        data: &frame_data[5..],
+    });
+}
+
+/** Maximum RF data of a Transmit Request. */
+pub const MAX_TX_DATA: usize = 100;
+
+pub struct TxRequest {
+    bytes: [u8; HEADER_LENGTH + 11 + MAX_TX_DATA + 1],
+    length: usize,
+}
+
+impl TxRequest {
+    /**
+     * Builds a 16-bit (0x01) or 64-bit (0x00) Transmit Request frame depending on the destination; the data is at
+     * most [`MAX_TX_DATA`] bytes. A non-zero frame id requests a Transmit Status.
+     */
+    pub fn new(frame_id: u8, destination: Address, options: u8, data: &[u8]) -> Result<TxRequest, Error> {
+        if data.len() > MAX_TX_DATA {
+            return Err(Error::DataTooLong(data.len()));
+        }
+        let api_identifier = match destination {
+            Address::Short(_) => ApiIdentifier::Tx16Req,
+            Address::Long(_) => ApiIdentifier::TxReq,
+        };
+        let address = destination.as_slice();
+        let length = 2 + address.len() + 1 + data.len();
+        let mut tx_request = TxRequest {
+            bytes: [0x00; HEADER_LENGTH + 11 + MAX_TX_DATA + 1],
+            length: HEADER_LENGTH + length + 1,
+        };
+        tx_request.bytes[0] = START_DELIMITER;
+        tx_request.bytes[1] = (length >> 8) as u8;
+        tx_request.bytes[2] = (length & 0x00ff) as u8;
+        tx_request.bytes[3] = api_identifier.value();
+        tx_request.bytes[4] = frame_id;
+        tx_request.bytes[5..5 + address.len()].copy_from_slice(address);
+        tx_request.bytes[5 + address.len()] = options;
+        tx_request.bytes[6 + address.len()..6 + address.len() + data.len()].copy_from_slice(data);
+        let sum = tx_request.bytes[HEADER_LENGTH..tx_request.length - 1].iter()
+            .fold(0u8, |acc, x| acc.wrapping_add(*x));
+        tx_request.bytes[tx_request.length - 1] = 0xFF - sum;
+        return Ok(tx_request);
+    }
+
+    pub fn as_slice(&self) -> &[u8] {
+        return &self.bytes[..self.length];
+    }
+}
+
+#[derive(PartialEq, Debug, Clone, Copy)]
+pub enum DeliveryStatus {
+    Success,
+    /** no acknowledgement received after all retries */
+    NoAck,
+    /** clear channel assessment failed */
+    CcaFailure,
+    /** coordinator timed out an indirect transmission */
+    Purged,
+    Other(u8),
+}
+
+impl DeliveryStatus {
+    pub fn from_value(value: u8) -> DeliveryStatus {
+        match value {
+            0x00 => DeliveryStatus::Success,
+            0x01 => DeliveryStatus::NoAck,
+            0x02 => DeliveryStatus::CcaFailure,
+            0x03 => DeliveryStatus::Purged,
+            value => DeliveryStatus::Other(value),
+        }
+    }
+}
+
+#[derive(PartialEq, Debug)]
+pub struct TxStatus {
+    pub frame_id: u8,
+    pub status: DeliveryStatus,
+}
+
+/** Parses a Transmit Status (0x89) frame. */
+pub fn parse_tx_status(frame: &[u8]) -> Result<TxStatus, Error> {
+    let frame_data = frame_data(frame)?;
+    if frame_data[0] != ApiIdentifier::TxStatus.value() {
+        return Err(Error::UnsupportedApiIdentifier(frame_data[0]));
+    }
+    if frame_data.len() < 3 {
+        return Err(Error::IncompleteFrame);
+    }
+    return Ok(TxStatus {
+        frame_id: frame_data[1],
+        status: DeliveryStatus::from_value(frame_data[2]),
+    });
+}
+
 #[cfg(test)]
 mod tests {
     use crate::ApiIdentifier::TxReq;
@@ -165,4 +493,167 @@ mod tests {
         }
         assert_eq!(actual.length, max_i + 1);
     }
+
+    #[test]
+    fn frame_length_from_header() {
+        assert_eq!(frame_length(&[0x7e, 0x00, 0x09]), Ok(13));
+        assert_eq!(frame_length(&[0x00, 0x00, 0x09]), Err(Error::NoStartDelimiter));
+        assert_eq!(frame_length(&[0x7e, 0x00]), Err(Error::IncompleteFrame));
+    }
+
+    #[test]
+    fn rx_packet_16_bit() {
+        let actual = parse_rx_packet(&[
+            0x7e, // start
+            0x00, 0x09, // len
+            0x81, // api_identifier
+            0x12, 0x34, // src
+            0x28, // rssi
+            0x00, // options
+            0x4e, 0x85, 0x68, 0x3a, // data
+            0x9b, // checksum
+        ]);
+        assert_eq!(actual, Ok(RxPacket {
+            source: Address::Short([0x12, 0x34]),
+            rssi: 0x28,
+            options: 0x00,
+            data: &[0x4e, 0x85, 0x68, 0x3a],
+        }));
+    }
+
+    #[test]
+    fn rx_packet_64_bit() {
+        let actual = parse_rx_packet(&[
+            0x7e, // start
+            0x00, 0x0f, // len
+            0x80, // api_identifier
+            0x00, 0x13, 0xA2, 0x00, 0x40, 0x64, 0x73, 0x46, // src
+            0x28, // rssi
+            0x00, // options
+            0x4e, 0x85, 0x68, 0x3a, // data
+            0xd0, // checksum
+        ]);
+        assert_eq!(actual, Ok(RxPacket {
+            source: Address::Long([0x00, 0x13, 0xA2, 0x00, 0x40, 0x64, 0x73, 0x46]),
+            rssi: 0x28,
+            options: 0x00,
+            data: &[0x4e, 0x85, 0x68, 0x3a],
+        }));
+    }
+
+    #[test]
+    fn rx_packet_invalid_checksum() {
+        let actual = parse_rx_packet(&[0x7e, 0x00, 0x09, 0x81, 0x12, 0x34, 0x28, 0x00, 0x4e, 0x85, 0x68, 0x3a, 0x9c]);
+        assert_eq!(actual, Err(Error::InvalidChecksum));
+    }
+
+    #[test]
+    fn rx_packet_unsupported_api_identifier() {
+        let actual = parse_rx_packet(&[0x7e, 0x00, 0x02, 0x8a, 0x00, 0x75]);
+        assert_eq!(actual, Err(Error::UnsupportedApiIdentifier(0x8a)));
+    }
+
+    #[test]
+    fn api_identifier_of_frame() {
+        assert_eq!(api_identifier(&[0x7e, 0x00, 0x02, 0x8a, 0x06, 0x6f]), Ok(ApiIdentifier::ModemStatus));
+        assert_eq!(api_identifier(&[0x7e, 0x00, 0x02, 0x8b, 0x06, 0x6e]), Err(Error::UnsupportedApiIdentifier(0x8b)));
+        assert_eq!(api_identifier(&[0x7e, 0x00, 0x02, 0x8a, 0x06, 0x6e]), Err(Error::InvalidChecksum));
+    }
+
+    #[test]
+    fn modem_status() {
+        let actual = parse_modem_status(&[
+            0x7e, // start
+            0x00, 0x02, // len
+            0x8a, // api_identifier
+            0x06, // status
+            0x6f, // checksum
+        ]);
+        assert_eq!(actual, Ok(ModemStatus::CoordinatorStarted));
+    }
+
+    #[test]
+    fn at_command() {
+        let actual = AtCommand::new(0x01, *b"DB", &[]).unwrap();
+        assert_eq!(actual.as_slice(), [
+            0x7e, // start
+            0x00, 0x04, // len
+            0x08, // api_identifier
+            0x01, // api_frame_id
+            0x44, 0x42, // command
+            0x70, // checksum
+        ]);
+    }
+
+    #[test]
+    fn at_command_with_parameter() {
+        let actual = AtCommand::new(0x52, *b"NJ", &[]).unwrap();
+        assert_eq!(actual.as_slice(), [0x7e, 0x00, 0x04, 0x08, 0x52, 0x4e, 0x4a, 0x0d]); // taken from the XBee manual
+        let actual = AtCommand::new(0x52, *b"NJ", &[0xff]).unwrap();
+        assert_eq!(actual.as_slice(), [0x7e, 0x00, 0x05, 0x08, 0x52, 0x4e, 0x4a, 0xff, 0x0e]);
+        let actual = AtCommand::new(0x52, *b"NI", &[0x41; MAX_AT_PARAMETER]).unwrap();
+        assert_eq!(actual.as_slice().len(), HEADER_LENGTH + 4 + MAX_AT_PARAMETER + 1);
+        assert!(matches!(AtCommand::new(0x52, *b"NI", &[0x41; MAX_AT_PARAMETER + 1]), Err(Error::ParameterTooLong(9))));
+    }
+
+    #[test]
+    fn at_command_response() {
+        let actual = parse_at_command_response(&[
+            0x7e, // start
+            0x00, 0x06, // len
+            0x88, // api_identifier
+            0x01, // api_frame_id
+            0x44, 0x42, // command
+            0x00, // status
+            0x28, // data
+            0xc8, // checksum
+        ]);
+        assert_eq!(actual, Ok(AtCommandResponse {
+            frame_id: 0x01,
+            command: *b"DB",
+            status: 0x00,
+            data: &[0x28],
+        }));
+    }
+
+    #[test]
+    fn tx_request_short() {
+        let actual = TxRequest::new(0x02, Address::Short([0x56, 0x78]), 0x00, &[0x01]).unwrap();
+        assert_eq!(actual.as_slice(), [
+            0x7e, // start
+            0x00, 0x06, // len
+            0x01, // api_identifier
+            0x02, // api_frame_id
+            0x56, 0x78, // destination
+            0x00, // options
+            0x01, // data
+            0x2d, // checksum
+        ]);
+    }
+
+    #[test]
+    fn tx_request_long() {
+        let actual = TxRequest::new(0x00, Address::Long([0x00, 0x13, 0xA2, 0x00, 0x40, 0x64, 0x03, 0x75]), 0x00,
+                                    &[0xff, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0xff]).unwrap();
+        let expected = Packet::new(TxReq, [0x00, 0x13, 0xA2, 0x00, 0x40, 0x64, 0x03, 0x75],
+                                   &[0xff, 0x41, 0x42, 0x43, 0x44, 0x45, 0x46, 0xff]);
+        assert!(actual.as_slice().iter().copied().eq(expected.iter()));
+        let actual = TxRequest::new(0x00, Address::Short([0x56, 0x78]), 0x00, &[0x41; MAX_TX_DATA]).unwrap();
+        assert_eq!(actual.as_slice().len(), HEADER_LENGTH + 5 + MAX_TX_DATA + 1);
+        assert!(matches!(TxRequest::new(0x00, Address::Short([0x56, 0x78]), 0x00, &[0x41; MAX_TX_DATA + 1]),
+                         Err(Error::DataTooLong(101))));
+    }
+
+    #[test]
+    fn tx_status() {
+        let actual = parse_tx_status(&[
+            0x7e, // start
+            0x00, 0x03, // len
+            0x89, // api_identifier
+            0x02, // api_frame_id
+            0x01, // status
+            0x73, // checksum
+        ]);
+        assert_eq!(actual, Ok(TxStatus { frame_id: 0x02, status: DeliveryStatus::NoAck }));
+    }
 }